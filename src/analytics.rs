@@ -0,0 +1,888 @@
+use crate::config::Settings;
+use crate::jobs::JobCtx;
+use anyhow::{anyhow, Result};
+use calamine::{Data, Ods, Reader, Xls, Xlsb, Xlsx};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json, Value};
+use sqlx::{query, query_as, Row, SqlitePool};
+use std::{
+    cmp::{max, min},
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+use time::{format_description::well_known::Iso8601, Date, Duration, OffsetDateTime};
+
+//表头最多在前几行内查找
+const HEADER_SEARCH_ROWS: usize = 10;
+//sale_record最多保留的天数
+const SALE_RECORD_DAYS: usize = 400;
+//未提交的预览保留的小时数
+const PREVIEW_KEEP_HOURS: i64 = 24;
+
+//流量表各列对应的标题, 可多个, 以"|"分隔
+pub struct ColumnTitles {
+    pub pid: String,
+    pub uv30: String,
+    pub sales30: String,
+    pub date: String, //可选列, 有此列时每行按自己的日期导入
+}
+impl ColumnTitles {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            pid: settings.xlsx_pid_column_title.clone(),
+            uv30: settings.xlsx_uv30_column_title.clone(),
+            sales30: settings.xlsx_sales30_column_title.clone(),
+            date: settings.xlsx_date_column_title.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RejectedRow {
+    pub row: usize, //表格中的行号, 从1开始
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub struct TrafficRow {
+    pub row: usize,
+    pub product_id: i64,
+    pub date: Option<Date>, //没有日期列时为None, 使用文件日期
+    pub uv: i64,
+    pub sale: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedSheet {
+    pub rows: Vec<TrafficRow>,
+    pub rejected: Vec<RejectedRow>,
+}
+
+//某天各product的(uv, sale)
+pub type DayRecords = HashMap<i64, (i64, i64)>;
+
+//单个文件的导入报告
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ImportReport {
+    pub file: String,
+    pub format: Option<SheetFormat>,
+    pub dates: Vec<String>,  //导入的日期, 文件中未出现的product这些天记为0
+    pub rows: usize,         //有效数据行数
+    pub matched: usize,      //匹配到product的行数
+    pub unmatched: Vec<i64>, //未匹配到product的product_id
+    pub rejected: Vec<RejectedRow>,
+}
+
+//已解析的上传文件
+pub struct ParsedUpload {
+    pub report: ImportReport,
+    pub days: BTreeMap<Date, DayRecords>,
+}
+
+//单个product的变更, uv30/sales30为(原值, 新值)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductChange {
+    pub id: i64,
+    pub product_id: i64,
+    pub uv30: (i64, i64),
+    pub sales30: (i64, i64),
+    pub days_changed: usize, //sale_record中新增或改变的天数
+    pub delist: bool,        //是否标记待下架(pending=-2)
+}
+impl ProductChange {
+    pub fn is_changed(&self) -> bool {
+        self.uv30.0 != self.uv30.1
+            || self.sales30.0 != self.sales30.1
+            || self.days_changed > 0
+            || self.delist
+    }
+}
+
+//预览, 保存在临时目录中, 可通过token提交
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Preview {
+    pub token: String,
+    pub reports: Vec<ImportReport>,
+    pub changes: Vec<StoredChange>,
+}
+
+//ProductChange不输出sale_record, 保存预览时需要完整数据
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoredChange {
+    #[serde(flatten)]
+    pub change: ProductChange,
+    pub old_sale_record: String,
+    pub sale_record: String,
+}
+
+fn title_matches(titles: &str, cell: &Data) -> bool {
+    match cell {
+        Data::String(s) => {
+            let s = s.trim();
+            !s.is_empty() && titles.contains(&format!("|{}|", s))
+        }
+        _ => false,
+    }
+}
+
+struct Header {
+    row: usize,
+    pid: usize,
+    uv30: usize,
+    sales30: usize,
+    date: Option<usize>,
+}
+
+//查找表头行, 缺少必需的列时报错
+fn find_header(rows: &[Vec<Data>], titles: &ColumnTitles) -> Result<Header> {
+    for (i, row) in rows.iter().take(HEADER_SEARCH_ROWS).enumerate() {
+        let find = |t: &str| row.iter().position(|c| title_matches(t, c));
+        let pid_i = match find(&titles.pid) {
+            Some(j) => j,
+            None => continue,
+        };
+        let mut missing = vec![];
+        let uv30_i = find(&titles.uv30);
+        if uv30_i.is_none() {
+            missing.push(titles.uv30.as_str());
+        }
+        let sales30_i = find(&titles.sales30);
+        if sales30_i.is_none() {
+            missing.push(titles.sales30.as_str());
+        }
+        return match (uv30_i, sales30_i) {
+            (Some(u), Some(s)) => Ok(Header {
+                row: i,
+                pid: pid_i,
+                uv30: u,
+                sales30: s,
+                date: find(&titles.date),
+            }),
+            _ => Err(anyhow!("表头缺少列: {}", missing.join(", "))),
+        };
+    }
+    Err(anyhow!("未找到表头, 缺少列: {}", titles.pid))
+}
+
+//数字单元格和文本单元格都可以
+pub fn cell_i64(cell: &Data) -> Option<i64> {
+    match cell {
+        Data::Int(i) => Some(*i),
+        Data::Float(f) if f.fract() == 0.0 => Some(*f as i64),
+        Data::String(s) => {
+            let s = s.trim().replace(',', "");
+            match s.parse::<i64>() {
+                Ok(i) => Some(i),
+                Err(_) => s
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|f| f.fract() == 0.0)
+                    .map(|f| f as i64),
+            }
+        }
+        _ => None,
+    }
+}
+
+fn metric(row: &[Data], i: usize, name: &str) -> Result<i64, String> {
+    match row.get(i) {
+        None | Some(Data::Empty) => Ok(0),
+        Some(cell) => match cell_i64(cell) {
+            Some(n) if n >= 0 => Ok(n),
+            Some(n) => Err(format!("{}为负数: {}", name, n)),
+            None => Err(format!("{}不是整数: {}", name, cell)),
+        },
+    }
+}
+
+//日期单元格可以是Excel日期或"YYYY-MM-DD"开头的文本
+fn cell_date(cell: &Data) -> Option<Date> {
+    match cell {
+        Data::DateTime(d) => {
+            //Excel日期序列号从1899-12-30起算
+            let base = Date::from_calendar_date(1899, time::Month::December, 30).ok()?;
+            base.checked_add(Duration::days(d.as_f64() as i64))
+        }
+        Data::DateTimeIso(s) | Data::String(s) => {
+            parse_date(s.trim().get(..10)?.replace('/', "-").as_str()).ok()
+        }
+        _ => None,
+    }
+}
+
+pub fn parse_rows(rows: &[Vec<Data>], titles: &ColumnTitles) -> Result<ParsedSheet> {
+    let header = find_header(rows, titles)?;
+    let (pid_i, uv30_i, sales30_i) = (header.pid, header.uv30, header.sales30);
+    let mut sheet = ParsedSheet::default();
+    let mut keys: HashSet<(i64, Option<Date>)> = HashSet::new();
+    for (i, row) in rows.iter().enumerate().skip(header.row + 1) {
+        if row.iter().all(|c| matches!(c, Data::Empty)) {
+            continue;
+        }
+        let mut reject = |reason: String| {
+            sheet.rejected.push(RejectedRow { row: i + 1, reason });
+        };
+        let pid = match row.get(pid_i).and_then(cell_i64) {
+            Some(pid) if pid > 0 => pid,
+            _ => {
+                reject(format!(
+                    "商品ID无效: {}",
+                    row.get(pid_i).unwrap_or(&Data::Empty)
+                ));
+                continue;
+            }
+        };
+        let uv = match metric(row, uv30_i, "访客数") {
+            Ok(n) => n,
+            Err(reason) => {
+                reject(reason);
+                continue;
+            }
+        };
+        let sale = match metric(row, sales30_i, "销量") {
+            Ok(n) => n,
+            Err(reason) => {
+                reject(reason);
+                continue;
+            }
+        };
+        let date = match header.date {
+            Some(j) => match row.get(j).and_then(cell_date) {
+                Some(d) => Some(d),
+                None => {
+                    reject(format!("日期无效: {}", row.get(j).unwrap_or(&Data::Empty)));
+                    continue;
+                }
+            },
+            None => None,
+        };
+        if !keys.insert((pid, date)) {
+            reject(format!("商品ID重复: {}", pid));
+            continue;
+        }
+        sheet.rows.push(TrafficRow {
+            row: i + 1,
+            product_id: pid,
+            date,
+            uv,
+            sale,
+        });
+    }
+    Ok(sheet)
+}
+
+pub fn parse_date(s: &str) -> Result<Date> {
+    Date::parse(s.trim(), &Iso8601::DATE).map_err(|e| anyhow!("日期格式错误({}): {}", s, e))
+}
+
+//日期范围, 首尾都包含
+pub type DateRange = (Date, Date);
+
+//"YYYY-MM-DD"或"YYYY-MM-DD~YYYY-MM-DD"
+pub fn parse_date_range(s: &str) -> Result<DateRange> {
+    let range = match s.split_once('~') {
+        Some((from, to)) => (parse_date(from)?, parse_date(to)?),
+        None => {
+            let d = parse_date(s)?;
+            (d, d)
+        }
+    };
+    check_range(range)
+}
+
+fn check_range(range: DateRange) -> Result<DateRange> {
+    let days = (range.1 - range.0).whole_days();
+    if days < 0 {
+        return Err(anyhow!("日期范围错误: {}~{}", range.0, range.1));
+    }
+    if days >= SALE_RECORD_DAYS as i64 {
+        return Err(anyhow!("日期范围超过{}天", SALE_RECORD_DAYS));
+    }
+    Ok(range)
+}
+
+//表单未提供日期时, 从文件名中获取, 包含两个日期时作为日期范围
+pub fn date_range_from_file_name(file_name: &str) -> Option<DateRange> {
+    let reg = Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap();
+    let dates: Vec<Date> = reg
+        .find_iter(file_name)
+        .filter_map(|m| parse_date(m.as_str()).ok())
+        .take(2)
+        .collect();
+    match dates[..] {
+        [d] => Some((d, d)),
+        [from, to] => check_range((min(from, to), max(from, to))).ok(),
+        _ => None,
+    }
+}
+
+fn range_dates(range: DateRange) -> Vec<Date> {
+    let mut dates = vec![];
+    let mut d = range.0;
+    while d <= range.1 {
+        dates.push(d);
+        d = match d.next_day() {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    dates
+}
+
+//文件数据为一段日期的合计时, 平均分到每一天, 余数分给靠前的日期
+fn spread(total: i64, days: usize, i: usize) -> i64 {
+    let days = days as i64;
+    total / days + if (i as i64) < total % days { 1 } else { 0 }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SheetFormat {
+    Csv,
+    Xls,
+    Xlsx,
+    Xlsb,
+    Ods,
+}
+
+//优先根据文件内容判断格式, 内容无法区分时再看扩展名
+pub fn detect_format(file_name: &str, data: &[u8]) -> Result<SheetFormat> {
+    let ext = Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if data.starts_with(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]) {
+        //OLE复合文档, 老版本Excel
+        return Ok(SheetFormat::Xls);
+    }
+    if data.starts_with(b"PK\x03\x04") {
+        //zip压缩包, ods第一个文件为未压缩的mimetype
+        if data.len() > 38 && data[30..38] == *b"mimetype" {
+            return Ok(SheetFormat::Ods);
+        }
+        return Ok(if ext == "xlsb" {
+            SheetFormat::Xlsb
+        } else {
+            SheetFormat::Xlsx
+        });
+    }
+    match ext.as_str() {
+        "csv" | "txt" | "" => Ok(SheetFormat::Csv),
+        "xls" | "xlsx" | "xlsb" | "ods" => Err(anyhow!("文件内容与扩展名不符: {}", ext)),
+        _ if !data.contains(&0) => Ok(SheetFormat::Csv),
+        _ => Err(anyhow!("不支持的文件格式: {}", ext)),
+    }
+}
+
+fn first_sheet<R: Reader<Cursor<Vec<u8>>>>(data: Vec<u8>) -> Result<Vec<Vec<Data>>> {
+    let mut workbook = R::new(Cursor::new(data)).map_err(|e| anyhow!("{:?}", e))?;
+    match workbook.worksheet_range_at(0) {
+        Some(range) => Ok(range
+            .map_err(|e| anyhow!("{:?}", e))?
+            .rows()
+            .map(|r| r.to_vec())
+            .collect()),
+        None => Err(anyhow!("没有工作表")),
+    }
+}
+
+//csv全部作为文本单元格, 非UTF-8时按GBK解码(Excel中文版导出)
+fn csv_rows(data: &[u8]) -> Result<Vec<Vec<Data>>> {
+    let text = match std::str::from_utf8(data) {
+        Ok(s) => s.trim_start_matches('\u{feff}').to_string(),
+        Err(_) => encoding_rs::GBK.decode(data).0.into_owned(),
+    };
+    let first_line = text.lines().next().unwrap_or("");
+    let delimiter = [b',', b';', b'\t']
+        .into_iter()
+        .max_by_key(|d| first_line.matches(*d as char).count())
+        .unwrap_or(b',');
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(text.as_bytes());
+    let mut rows = vec![];
+    for record in reader.records() {
+        rows.push(
+            record?
+                .iter()
+                .map(|c| {
+                    if c.trim().is_empty() {
+                        Data::Empty
+                    } else {
+                        Data::String(c.to_string())
+                    }
+                })
+                .collect(),
+        );
+    }
+    Ok(rows)
+}
+
+pub fn read_rows(format: SheetFormat, data: Vec<u8>) -> Result<Vec<Vec<Data>>> {
+    match format {
+        SheetFormat::Csv => csv_rows(&data),
+        SheetFormat::Xls => first_sheet::<Xls<_>>(data),
+        SheetFormat::Xlsx => first_sheet::<Xlsx<_>>(data),
+        SheetFormat::Xlsb => first_sheet::<Xlsb<_>>(data),
+        SheetFormat::Ods => first_sheet::<Ods<_>>(data),
+    }
+}
+
+//识别格式并解析第一个工作表
+//没有日期列的行使用range中的日期
+pub fn parse_upload(
+    file_name: &str,
+    data: Vec<u8>,
+    range: Option<DateRange>,
+    titles: &ColumnTitles,
+) -> Result<ParsedUpload> {
+    let format = detect_format(file_name, &data)?;
+    let rows = read_rows(format, data)?;
+    let mut parsed = parse_rows(&rows, titles)?;
+    let file_dates = range.map(range_dates).unwrap_or_default();
+    //只有没有日期列的行使用表单或文件名中的日期, 否则文件名中的日期会被记为全0
+    let mut days: BTreeMap<Date, DayRecords> = BTreeMap::new();
+    let mut rows_count = 0;
+    for row in parsed.rows {
+        match row.date {
+            Some(d) => {
+                days.entry(d)
+                    .or_default()
+                    .insert(row.product_id, (row.uv, row.sale));
+            }
+            None if file_dates.is_empty() => {
+                parsed.rejected.push(RejectedRow {
+                    row: row.row,
+                    reason: "没有日期, 请在表单或文件名中提供日期".to_string(),
+                });
+                continue;
+            }
+            None => {
+                for (i, d) in file_dates.iter().enumerate() {
+                    days.entry(*d).or_default().insert(
+                        row.product_id,
+                        (
+                            spread(row.uv, file_dates.len(), i),
+                            spread(row.sale, file_dates.len(), i),
+                        ),
+                    );
+                }
+            }
+        }
+        rows_count += 1;
+    }
+    days.retain(|_, records| !records.is_empty());
+    if let Some((first, _)) = days.first_key_value() {
+        check_range((*first, *days.last_key_value().unwrap().0))?;
+    }
+    parsed.rejected.sort_by_key(|r| r.row);
+    Ok(ParsedUpload {
+        report: ImportReport {
+            file: file_name.to_string(),
+            format: Some(format),
+            dates: days.keys().map(|d| d.to_string()).collect(),
+            rows: rows_count + parsed.rejected.len(),
+            rejected: parsed.rejected,
+            ..Default::default()
+        },
+        days,
+    })
+}
+
+//按日期写入sale_record, 已有的日期被替换, 日期从新到旧排列, 返回改变的天数
+fn merge_days(sale_record: &mut Vec<Value>, days: &BTreeMap<String, (i64, i64)>) -> usize {
+    let mut by_date: BTreeMap<String, Value> = sale_record
+        .drain(..)
+        .map(|v| (v["date"].as_str().unwrap_or("").to_string(), v))
+        .collect();
+    let mut changed = 0;
+    for (date, (uv, sale)) in days {
+        let entry = json!({"date": date, "sale": sale, "uv": uv});
+        if by_date.get(date) != Some(&entry) {
+            changed += 1;
+            by_date.insert(date.clone(), entry);
+        }
+    }
+    sale_record.extend(by_date.into_values().rev().take(SALE_RECORD_DAYS));
+    changed
+}
+
+//该商品在上传文件各日期的(uv, sale), 文件中没有该商品时只为原来没有记录的日期补0, 不覆盖已有的数据
+fn product_days(
+    days: &BTreeMap<Date, DayRecords>,
+    product_id: i64,
+    sale_record: &[Value],
+) -> BTreeMap<String, (i64, i64)> {
+    let recorded: HashSet<&str> = sale_record
+        .iter()
+        .filter_map(|v| v["date"].as_str())
+        .collect();
+    days.iter()
+        .filter_map(|(d, records)| {
+            let date = d.to_string();
+            match records.get(&product_id) {
+                Some(r) => Some((date, *r)),
+                None if !recorded.contains(date.as_str()) => Some((date, (0, 0))),
+                None => None,
+            }
+        })
+        .collect()
+}
+
+//截止今天的30天内的(uv, sale)合计
+fn sum30(sale_record: &[Value], today: Date) -> (i64, i64) {
+    let from = (today - Duration::days(29)).to_string();
+    let to = today.to_string();
+    sale_record
+        .iter()
+        .filter(|v| {
+            let d = v["date"].as_str().unwrap_or("");
+            d >= from.as_str() && d <= to.as_str()
+        })
+        .fold((0, 0), |(u, s), v| {
+            (
+                u + v["uv"].as_i64().unwrap_or(0),
+                s + v["sale"].as_i64().unwrap_or(0),
+            )
+        })
+}
+
+//遍历所有未删除的product, 合并各上传文件的数据, 计算变更但不写入
+//同一天出现在多个文件中时, 后面的文件优先
+pub async fn compute_changes(
+    db: &SqlitePool,
+    settings: &Settings,
+    uploads: &mut [ParsedUpload],
+    job: &JobCtx,
+) -> Result<Vec<StoredChange>> {
+    let barrier_uv30 = settings.unpublish_barrier_uv30;
+    let now = OffsetDateTime::now_local()?;
+    let today = now.date();
+    let days_before = now - Duration::days(settings.analysis_before);
+
+    let mut days: BTreeMap<Date, DayRecords> = BTreeMap::new();
+    for upload in uploads.iter() {
+        for (d, records) in &upload.days {
+            days.entry(*d)
+                .or_default()
+                .extend(records.iter().map(|(k, v)| (*k, *v)));
+        }
+    }
+
+    let total: (i64,) = query_as("select count(id) from products where deleted_at is null")
+        .fetch_one(db)
+        .await?;
+    job.message("计算变更").await?;
+    let mut seen: HashSet<i64> = HashSet::new();
+    let mut changes = vec![];
+    let mut current_id = 0;
+    loop {
+        let rows = query("select id,product_id,uv30,sales30,sale_record,created_at from products where id>? and deleted_at is null order by id asc limit 50")
+            .bind(current_id)
+            .fetch_all(db)
+            .await?;
+        if rows.is_empty() {
+            break;
+        }
+        for row in rows {
+            let id: i64 = row.get("id");
+            current_id = id;
+            let product_id: i64 = row.get("product_id");
+            let old_sale_record: String = row.get("sale_record");
+            let created_at: OffsetDateTime = row.get("created_at");
+            //格式错误时不能当作没有记录, 否则写入后历史数据全部丢失
+            let mut sale_record = if old_sale_record.trim().is_empty() {
+                vec![]
+            } else {
+                from_str::<Vec<Value>>(&old_sale_record).map_err(|e| {
+                    anyhow!(
+                        "product {} 的sale_record格式错误, 请先修正: {}",
+                        product_id,
+                        e
+                    )
+                })?
+            };
+            let product_days = product_days(&days, product_id, &sale_record);
+            let days_changed = merge_days(&mut sale_record, &product_days);
+            seen.insert(product_id);
+            let (uv30, sales30) = sum30(&sale_record, today);
+            changes.push(StoredChange {
+                change: ProductChange {
+                    id,
+                    product_id,
+                    uv30: (row.get("uv30"), uv30),
+                    sales30: (row.get("sales30"), sales30),
+                    days_changed,
+                    delist: created_at < days_before && uv30 < barrier_uv30,
+                },
+                old_sale_record,
+                sale_record: json!(sale_record).to_string(),
+            });
+        }
+        job.progress(changes.len() as i64, total.0).await?;
+    }
+
+    for upload in uploads.iter_mut() {
+        let pids: HashSet<i64> = upload
+            .days
+            .values()
+            .flat_map(|records| records.keys().copied())
+            .collect();
+        upload.report.matched = pids.iter().filter(|pid| seen.contains(pid)).count();
+        upload.report.unmatched = pids.into_iter().filter(|pid| !seen.contains(pid)).collect();
+        upload.report.unmatched.sort();
+    }
+    Ok(changes)
+}
+
+//写入变更, 计算后sale_record已被修改的product不更新, 返回(更新数, 冲突的product_id)
+pub async fn apply_changes(
+    db: &SqlitePool,
+    changes: &[StoredChange],
+    job: &JobCtx,
+) -> Result<(usize, Vec<i64>)> {
+    let now = OffsetDateTime::now_local()?;
+    let sql_str =
+        "update products set uv30=?,sales30=?,sale_record=?,updated_at=? where id=? and sale_record=?";
+    let sql_str_2 = "update products set uv30=?,sales30=?,sale_record=?,updated_at=?,pending=-2 where id=? and sale_record=?";
+    let mut updated = 0;
+    let mut conflicts = vec![];
+
+    //事务中不能更新任务进度, 任务表的写入会等待事务结束
+    job.message("写入变更").await?;
+    job.progress(0, changes.len() as i64).await?;
+    let mut db_trans = db.begin().await?;
+    for c in changes {
+        let affacted_rows = if c.change.delist {
+            query(sql_str_2)
+        } else {
+            query(sql_str)
+        }
+        .bind(c.change.uv30.1)
+        .bind(c.change.sales30.1)
+        .bind(&c.sale_record)
+        .bind(now)
+        .bind(c.change.id)
+        .bind(&c.old_sale_record)
+        .execute(&mut *db_trans)
+        .await?
+        .rows_affected();
+        if affacted_rows > 0 {
+            updated += 1;
+        } else {
+            conflicts.push(c.change.product_id);
+        }
+    }
+    db_trans.commit().await?;
+    job.progress(changes.len() as i64, changes.len() as i64)
+        .await?;
+
+    Ok((updated, conflicts))
+}
+
+//没有新数据时按当前日期重新计算uv30/sales30, 并检查是否需要下架
+pub async fn recompute_traffic(
+    db: &SqlitePool,
+    settings: &Settings,
+    job: &JobCtx,
+) -> Result<Value> {
+    let changes = compute_changes(db, settings, &mut [], job).await?;
+    let (updated, conflicts) = apply_changes(db, &changes, job).await?;
+    Ok(json!({
+        "updated": updated,
+        "conflicts": conflicts,
+        "delisted": changes.iter().filter(|c| c.change.delist).map(|c| c.change.product_id).collect::<Vec<i64>>(),
+    }))
+}
+
+fn preview_path(tmp_dir: &str, token: &str) -> Result<PathBuf> {
+    if token.is_empty() || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("无效的token: {}", token));
+    }
+    Ok(PathBuf::from(tmp_dir).join(format!("upload-preview-{}.json", token)))
+}
+
+//删除过期未提交的预览
+fn expire_previews(tmp_dir: &str) -> Result<()> {
+    let keep = std::time::Duration::from_secs(PREVIEW_KEEP_HOURS as u64 * 3600);
+    for entry in fs::read_dir(tmp_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with("upload-preview-") || !name.ends_with(".json") {
+            continue;
+        }
+        let expired = entry
+            .metadata()?
+            .modified()?
+            .elapsed()
+            .is_ok_and(|age| age > keep);
+        if expired {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+//只保存有变化的product, 同时清理过期的预览
+pub fn save_preview(
+    tmp_dir: &str,
+    reports: Vec<ImportReport>,
+    changes: Vec<StoredChange>,
+) -> Result<Preview> {
+    expire_previews(tmp_dir)?;
+    let changes = changes
+        .into_iter()
+        .filter(|c| c.change.is_changed())
+        .collect();
+    let token = format!("{:x}", OffsetDateTime::now_utc().unix_timestamp_nanos());
+    let preview = Preview {
+        token,
+        reports,
+        changes,
+    };
+    fs::write(
+        preview_path(tmp_dir, &preview.token)?,
+        serde_json::to_string(&preview)?,
+    )?;
+    Ok(preview)
+}
+
+//读取预览, 读取后即删除, 同一预览只能提交一次
+pub fn take_preview(tmp_dir: &str, token: &str) -> Result<Preview> {
+    let path = preview_path(tmp_dir, token)?;
+    let preview: Preview = match fs::read_to_string(&path) {
+        Ok(s) => from_str(&s)?,
+        Err(_) => {
+            return Err(anyhow!("预览不存在或已提交: {}", token));
+        }
+    };
+    fs::remove_file(&path)?;
+    Ok(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles() -> ColumnTitles {
+        ColumnTitles::from_settings(&Settings::default())
+    }
+
+    fn date(s: &str) -> Date {
+        parse_date(s).unwrap()
+    }
+
+    fn upload(file_name: &str, csv: &str) -> ParsedUpload {
+        let range = date_range_from_file_name(file_name);
+        parse_upload(file_name, csv.as_bytes().to_vec(), range, &titles()).unwrap()
+    }
+
+    #[test]
+    fn date_column_ignores_file_name_date() {
+        let parsed = upload(
+            "traffic-2026-10-01.csv",
+            "商品ID,访客数,支付商品件数,日期\n1,5,2,2026-09-29\n2,3,1,2026-09-30\n",
+        );
+        assert_eq!(parsed.report.dates, vec!["2026-09-29", "2026-09-30"]);
+        assert!(!parsed.days.contains_key(&date("2026-10-01")));
+        assert_eq!(parsed.days[&date("2026-09-29")][&1], (5, 2));
+        assert_eq!(parsed.days[&date("2026-09-30")][&2], (3, 1));
+    }
+
+    #[test]
+    fn file_name_range_spreads_rows() {
+        let parsed = upload(
+            "traffic-2026-10-01~2026-10-03.csv",
+            "商品ID,访客数,支付商品件数\n1,10,4\n",
+        );
+        assert_eq!(parsed.report.dates.len(), 3);
+        let per_day: Vec<(i64, i64)> = parsed.days.values().map(|r| r[&1]).collect();
+        assert_eq!(per_day, vec![(4, 2), (3, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn rows_without_any_date_are_rejected() {
+        let parsed = upload("traffic.csv", "商品ID,访客数,支付商品件数\n1,10,4\n");
+        assert!(parsed.days.is_empty());
+        assert_eq!(parsed.report.rejected.len(), 1);
+        assert_eq!(parsed.report.rejected[0].row, 2);
+    }
+
+    #[test]
+    fn missing_product_keeps_existing_days() {
+        let parsed = upload(
+            "traffic-2026-10-01~2026-10-02.csv",
+            "商品ID,访客数,支付商品件数\n1,4,2\n",
+        );
+        let mut sale_record = vec![json!({"date": "2026-10-01", "sale": 3, "uv": 9})];
+        let days = product_days(&parsed.days, 2, &sale_record);
+        assert_eq!(days.len(), 1);
+        assert_eq!(days["2026-10-02"], (0, 0));
+        assert_eq!(merge_days(&mut sale_record, &days), 1);
+        assert_eq!(sale_record.len(), 2);
+        assert_eq!(
+            sale_record[1],
+            json!({"date": "2026-10-01", "sale": 3, "uv": 9})
+        );
+        let days = product_days(&parsed.days, 1, &sale_record);
+        assert_eq!(days["2026-10-01"], (2, 1));
+    }
+
+    #[test]
+    fn date_range() {
+        assert_eq!(
+            parse_date_range("2026-10-01").unwrap(),
+            (date("2026-10-01"), date("2026-10-01"))
+        );
+        assert_eq!(
+            parse_date_range("2026-10-01~2026-10-05").unwrap(),
+            (date("2026-10-01"), date("2026-10-05"))
+        );
+        assert!(parse_date_range("2026-10-05~2026-10-01").is_err());
+        assert!(parse_date_range("2024-01-01~2026-01-01").is_err());
+        assert!(parse_date_range("2026/10/01").is_err());
+    }
+
+    #[test]
+    fn detect_format_by_content() {
+        let ole = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1, 0, 0];
+        assert_eq!(detect_format("a.xlsx", &ole).unwrap(), SheetFormat::Xls);
+        let mut zip = b"PK\x03\x04".to_vec();
+        zip.resize(30, 0);
+        assert_eq!(detect_format("a.xls", &zip).unwrap(), SheetFormat::Xlsx);
+        assert_eq!(detect_format("a.XLSB", &zip).unwrap(), SheetFormat::Xlsb);
+        zip.extend_from_slice(b"mimetypeapplication/vnd.oasis.opendocument.spreadsheet");
+        assert_eq!(detect_format("a.xlsx", &zip).unwrap(), SheetFormat::Ods);
+    }
+
+    #[test]
+    fn detect_format_by_extension() {
+        assert_eq!(detect_format("a.csv", b"1,2").unwrap(), SheetFormat::Csv);
+        assert_eq!(detect_format("a", b"1,2").unwrap(), SheetFormat::Csv);
+        assert_eq!(detect_format("a.dat", b"1,2").unwrap(), SheetFormat::Csv);
+        assert!(detect_format("a.xlsx", b"1,2").is_err());
+        assert!(detect_format("a.dat", b"1\x002").is_err());
+    }
+
+    #[test]
+    fn gbk_csv_is_decoded() {
+        let data = encoding_rs::GBK
+            .encode("商品ID;访客数\n1;10\n")
+            .0
+            .into_owned();
+        let rows = csv_rows(&data).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vec![Data::String("商品ID".into()), Data::String("访客数".into())],
+                vec![Data::String("1".into()), Data::String("10".into())],
+            ]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::config::Settings;
+use anyhow::{anyhow, Result};
+use serde_json::{from_str, Value};
+use sqlx::pool::PoolConnection;
+use sqlx::{query, query_as, Connection, QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use std::path::Path;
+use time::{Date, Duration};
+
+//可归档的表, 及查询时按id筛选的字段
+pub const TABLES: &[(&str, &str)] = &[
+    ("offers", "offer_id"),
+    ("products", "product_id"),
+    ("orders", "order_id"),
+    ("order_items", "order_id"),
+    ("shipments", "order_id"),
+    ("tracking_events", "order_id"),
+    ("order_notes", "order_id"),
+    ("offer_products", "offer_id"),
+    ("sku_mappings", "product_id"),
+    ("offer_events", "offer_id"),
+    ("offer_switches", "product_id"),
+];
+
+//随订单一起归档的表
+pub const ORDER_TABLES: &[&str] = &["order_items", "shipments", "tracking_events", "order_notes"];
+//随offer一起归档的表, 切换记录属于商品, 随商品归档
+pub const OFFER_TABLES: &[&str] = &["offer_products", "sku_mappings", "offer_events"];
+//随商品一起归档的表
+pub const PRODUCT_TABLES: &[&str] = &["offer_products", "sku_mappings", "offer_switches"];
+
+//归档数据库文件, 默认archive.db
+pub fn path(settings: &Settings) -> String {
+    settings.archive_db.clone()
+}
+
+//RETENTION_ARCHIVE中为false的表超过保留天数直接删除, 未配置的归档
+pub fn enabled(settings: &Settings, table: &str) -> bool {
+    settings
+        .retention_archive
+        .get(table)
+        .copied()
+        .unwrap_or(true)
+}
+
+//ATTACH只对当前连接有效, 用完需要detach再放回连接池
+pub async fn attach(db: &SqlitePool, settings: &Settings) -> Result<PoolConnection<Sqlite>> {
+    let mut conn = db.acquire().await?;
+    //上次出错时可能没有detach
+    let _ = query("DETACH DATABASE archive").execute(&mut *conn).await;
+    query("ATTACH DATABASE ? AS archive")
+        .bind(path(settings))
+        .execute(&mut *conn)
+        .await?;
+    Ok(conn)
+}
+
+pub async fn detach(conn: &mut SqliteConnection) -> Result<()> {
+    query("DETACH DATABASE archive").execute(&mut *conn).await?;
+    Ok(())
+}
+
+async fn columns(conn: &mut SqliteConnection, schema: &str, table: &str) -> Result<Vec<String>> {
+    let cols: Vec<(String,)> = query_as("select name from pragma_table_info(?, ?) order by cid")
+        .bind(table)
+        .bind(schema)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(cols.into_iter().map(|c| c.0).collect())
+}
+
+//归档表和原表同名, 原表增加的字段在归档表中补上
+async fn prepare(conn: &mut SqliteConnection, table: &str) -> Result<Vec<String>> {
+    let cols = columns(conn, "main", table).await?;
+    if cols.is_empty() {
+        return Err(anyhow!("没有该表: {}", table));
+    }
+    let archived = columns(conn, "archive", table).await?;
+    if archived.is_empty() {
+        query(&format!(
+            "create table archive.{0} as select * from main.{0} where 0",
+            table
+        ))
+        .execute(&mut *conn)
+        .await?;
+    } else {
+        for col in cols.iter().filter(|c| !archived.contains(c)) {
+            query(&format!("alter table archive.{} add column {}", table, col))
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    Ok(cols)
+}
+
+//把符合条件的行移到归档表, cond中的?1为截止日期, archive为false时只删除
+pub async fn move_rows(
+    conn: &mut SqliteConnection,
+    table: &str,
+    cond: &str,
+    before: Date,
+    archive: bool,
+) -> Result<u64> {
+    if archive {
+        let cols = prepare(conn, table).await?.join(",");
+        query(&format!(
+            "insert into archive.{0} ({1}) select {1} from main.{0} where {2}",
+            table, cols, cond
+        ))
+        .bind(before)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(query(&format!("delete from main.{} where {}", table, cond))
+        .bind(before)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected())
+}
+
+//查询归档数据, id按表的id字段筛选, 日期为创建时间
+pub struct Search {
+    pub table: String,
+    pub id: Option<i64>,
+    pub product_id: Option<i64>,
+    pub range: Option<(Date, Date)>,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+pub async fn search(
+    db: &SqlitePool,
+    settings: &Settings,
+    search: &Search,
+) -> Result<(i64, Vec<Value>)> {
+    let id_col = match TABLES.iter().find(|t| t.0 == search.table) {
+        Some(t) => t.1,
+        None => {
+            return Err(anyhow!("不能查询该表: {}", search.table));
+        }
+    };
+    if !Path::new(&path(settings)).exists() {
+        return Ok((0, vec![]));
+    }
+    let mut conn = attach(db, settings).await?;
+    let res = search_attached(&mut conn, id_col, search).await;
+    detach(&mut conn).await?;
+    res
+}
+
+async fn search_attached(
+    conn: &mut SqliteConnection,
+    id_col: &str,
+    search: &Search,
+) -> Result<(i64, Vec<Value>)> {
+    let cols = columns(conn, "archive", &search.table).await?;
+    if cols.is_empty() {
+        return Ok((0, vec![]));
+    }
+    //字段不固定, 用json_object转成json
+    let json_cols = cols
+        .iter()
+        .map(|c| format!("'{0}',{0}", c))
+        .collect::<Vec<String>>()
+        .join(",");
+    let mut total_query_builder = QueryBuilder::new(format!(
+        "select count(*) from archive.{} where 1=1",
+        search.table
+    ));
+    let mut rows_query_builder = QueryBuilder::new(format!(
+        "select json_object({}) from archive.{} where 1=1",
+        json_cols, search.table
+    ));
+    for query_builder in [&mut total_query_builder, &mut rows_query_builder] {
+        if let Some(id) = search.id {
+            query_builder.push(format!(" and {} = ", id_col));
+            query_builder.push_bind(id);
+        }
+        if let Some(product_id) = search.product_id {
+            if cols.iter().any(|c| c == "product_id") {
+                query_builder.push(" and product_id = ");
+            } else if search.table == "orders" {
+                query_builder.push(
+                    " and order_id in (select order_id from archive.order_items where product_id = ",
+                );
+            } else {
+                return Err(anyhow!("该表不能按product_id查询"));
+            }
+            query_builder.push_bind(product_id);
+            if search.table == "orders" {
+                query_builder.push(")");
+            }
+        }
+        if let Some((from, to)) = search.range {
+            query_builder.push(" and created_at >= ");
+            query_builder.push_bind(from);
+            query_builder.push(" and created_at < ");
+            query_builder.push_bind(to + Duration::days(1));
+        }
+    }
+    let total: (i64,) = total_query_builder
+        .build_query_as()
+        .fetch_one(&mut *conn)
+        .await?;
+    rows_query_builder.push(" order by created_at desc limit ");
+    rows_query_builder.push_bind(search.per_page);
+    rows_query_builder.push(" offset ");
+    rows_query_builder.push_bind((search.page - 1) * search.per_page);
+    let rows: Vec<(String,)> = rows_query_builder
+        .build_query_as()
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut res = vec![];
+    for (row,) in rows {
+        res.push(from_str(&row)?);
+    }
+    Ok((total.0, res))
+}
+
+//清理用的连接, 归档时attach归档数据库, 用完调用close
+pub async fn open(
+    db: &SqlitePool,
+    settings: &Settings,
+    archive: bool,
+) -> Result<PoolConnection<Sqlite>> {
+    if archive {
+        attach(db, settings).await
+    } else {
+        Ok(db.acquire().await?)
+    }
+}
+
+pub async fn close(conn: &mut SqliteConnection, archive: bool) -> Result<()> {
+    if archive {
+        detach(conn).await?;
+    }
+    Ok(())
+}
+
+//按顺序处理多个表, pre为之前在同一事务中执行的语句(?1为截止日期), 返回各表删除的行数
+pub async fn purge(
+    db: &SqlitePool,
+    settings: &Settings,
+    pre: &[&str],
+    tables: &[(&str, String)],
+    before: Date,
+    archive: bool,
+) -> Result<Vec<u64>> {
+    let mut conn = open(db, settings, archive).await?;
+    let res = purge_on(&mut conn, pre, tables, before, archive).await;
+    close(&mut conn, archive).await?;
+    res
+}
+
+async fn purge_on(
+    conn: &mut SqliteConnection,
+    pre: &[&str],
+    tables: &[(&str, String)],
+    before: Date,
+    archive: bool,
+) -> Result<Vec<u64>> {
+    let mut db_trans = conn.begin().await?;
+    let deleted = move_tables(&mut db_trans, pre, tables, before, archive).await?;
+    db_trans.commit().await?;
+    Ok(deleted)
+}
+
+//同purge, 在调用方的事务中执行
+pub async fn move_tables(
+    conn: &mut SqliteConnection,
+    pre: &[&str],
+    tables: &[(&str, String)],
+    before: Date,
+    archive: bool,
+) -> Result<Vec<u64>> {
+    for sql in pre {
+        query(sql).bind(before).execute(&mut *conn).await?;
+    }
+    let mut deleted = vec![];
+    for (table, cond) in tables {
+        deleted.push(move_rows(conn, table, cond, before, archive).await?);
+    }
+    Ok(deleted)
+}
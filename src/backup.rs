@@ -0,0 +1,214 @@
+use crate::config::Settings;
+use crate::migrations;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{query, query_as, Connection, SqliteConnection, SqlitePool};
+use std::{fs, path::PathBuf};
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+use tracing::info;
+
+//备份文件名 ae-YYYYMMDD-HHMMSS.db, 同一秒内的再次备份加 _N, 按文件名排序即按时间排序
+const PREFIX: &str = "ae-";
+const SUFFIX: &str = ".db";
+//文件名被同时进行的备份占用时的重试次数
+const NAME_RETRIES: usize = 10;
+
+#[derive(Serialize)]
+pub struct BackupFile {
+    pub file: String,
+    pub size: u64,
+    #[serde(with = "show_time")]
+    pub modified: OffsetDateTime,
+}
+
+fn dir(settings: &Settings) -> PathBuf {
+    PathBuf::from(&settings.backup_dir)
+}
+
+//使用VACUUM INTO备份数据库, 不影响正在进行的读写, 备份后删除多余的旧备份
+pub async fn create(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let mut res = vacuum_into(db, settings).await?;
+    res["removed"] = json!(rotate(settings)?);
+    Ok(res)
+}
+
+//只备份不删除旧备份, 恢复前使用, 避免删除要恢复的备份文件
+async fn vacuum_into(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let backup_dir = dir(settings);
+    fs::create_dir_all(&backup_dir)?;
+    let now = OffsetDateTime::now_local()?;
+    let stamp = format!(
+        "{}{:04}{:02}{:02}-{:02}{:02}{:02}",
+        PREFIX,
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+    );
+    //序号比同一秒已有的备份大, 不重用已删除的备份的文件名, 保证按文件名排序即按时间排序
+    let start = list(settings)?
+        .iter()
+        .filter_map(|f| {
+            let n = f.file.strip_prefix(&stamp)?.strip_suffix(SUFFIX)?;
+            match n.strip_prefix('_') {
+                Some(n) => n.parse::<usize>().ok(),
+                None if n.is_empty() => Some(0),
+                None => None,
+            }
+        })
+        .max()
+        .map_or(0, |n| n + 1);
+    for n in start..start + NAME_RETRIES {
+        let file_name = if n == 0 {
+            format!("{}{}", stamp, SUFFIX)
+        } else {
+            format!("{}_{}{}", stamp, n, SUFFIX)
+        };
+        let file_path = backup_dir.join(&file_name);
+        if file_path.exists() {
+            continue;
+        }
+        match query("VACUUM INTO ?")
+            .bind(file_path.to_string_lossy().to_string())
+            .execute(db)
+            .await
+        {
+            Ok(_) => {}
+            //同时进行的备份已使用该文件名
+            Err(_) if file_path.exists() => continue,
+            Err(e) => return Err(e.into()),
+        }
+        info!("database backup created: {}", file_path.display());
+        return Ok(json!({
+            "file": file_name,
+            "size": fs::metadata(&file_path)?.len(),
+            "schema_version": migrations::schema_version(db).await?,
+        }));
+    }
+    Err(anyhow!("备份文件名已被占用, 请稍后重试"))
+}
+
+//只保留最新的BACKUP_MAX_FILES个备份, 同max_log_files, 为0时不删除
+fn rotate(settings: &Settings) -> Result<Vec<String>> {
+    let max_files = settings.backup_max_files;
+    let mut removed = vec![];
+    if max_files == 0 {
+        return Ok(removed);
+    }
+    let files = list(settings)?;
+    for f in files.iter().skip(max_files) {
+        fs::remove_file(dir(settings).join(&f.file))?;
+        info!("old database backup removed: {}", f.file);
+        removed.push(f.file.clone());
+    }
+    Ok(removed)
+}
+
+//备份文件, 最新的在前
+pub fn list(settings: &Settings) -> Result<Vec<BackupFile>> {
+    let backup_dir = dir(settings);
+    if !backup_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut files = vec![];
+    for entry in fs::read_dir(&backup_dir)? {
+        let entry = entry?;
+        let file = entry.file_name().to_string_lossy().to_string();
+        if !file.starts_with(PREFIX) || !file.ends_with(SUFFIX) {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        files.push(BackupFile {
+            file,
+            size: meta.len(),
+            modified: OffsetDateTime::from(meta.modified()?),
+        });
+    }
+    files.sort_by(|a, b| b.file.cmp(&a.file));
+    Ok(files)
+}
+
+//用备份文件覆盖当前数据, 备份的结构版本必须和当前一致
+//恢复前先备份当前数据(不删除旧备份), 整个过程在一个事务中, 失败时不改变当前数据
+pub async fn restore(db: &SqlitePool, settings: &Settings, file: &str) -> Result<Value> {
+    if !list(settings)?.iter().any(|f| f.file == file) {
+        return Err(anyhow!("没有该备份文件: {}", file));
+    }
+    let file_path = dir(settings).join(file);
+    let mut conn = db.acquire().await?;
+    let _ = query("DETACH DATABASE restore").execute(&mut *conn).await;
+    query("ATTACH DATABASE ? AS restore")
+        .bind(file_path.to_string_lossy().to_string())
+        .execute(&mut *conn)
+        .await?;
+    let res = restore_attached(db, settings, &mut conn).await;
+    query("DETACH DATABASE restore").execute(&mut *conn).await?;
+    let (before, tables) = res?;
+    info!("database restored from backup: {}", file);
+    Ok(json!({
+        "file": file,
+        "before_restore": before["file"],
+        "tables": tables,
+    }))
+}
+
+async fn restore_attached(
+    db: &SqlitePool,
+    settings: &Settings,
+    conn: &mut SqliteConnection,
+) -> Result<(Value, Value)> {
+    check(conn).await?;
+    let before = vacuum_into(db, settings).await?;
+    Ok((before, copy(conn).await?))
+}
+
+async fn check(conn: &mut SqliteConnection) -> Result<()> {
+    let (version,): (i64,) = query_as("PRAGMA restore.user_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    if version != migrations::SCHEMA_VERSION {
+        return Err(anyhow!(
+            "备份的数据库版本为{}, 当前为{}, 不能恢复",
+            version,
+            migrations::SCHEMA_VERSION
+        ));
+    }
+    let (result,): (String,) = query_as("PRAGMA restore.quick_check")
+        .fetch_one(&mut *conn)
+        .await?;
+    if result != "ok" {
+        return Err(anyhow!("备份文件已损坏: {}", result));
+    }
+    Ok(())
+}
+
+//版本相同时表结构相同, 按表整体替换, 任务记录不恢复(包括正在执行的恢复任务)
+async fn copy(conn: &mut SqliteConnection) -> Result<Value> {
+    let tables: Vec<(String,)> = query_as(
+        "select name from main.sqlite_master where type='table' and name not like 'sqlite_%' and name != 'jobs' order by name",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut counts = json!({});
+    let mut db_trans = conn.begin().await?;
+    for (table,) in tables {
+        query(&format!("delete from main.{}", table))
+            .execute(&mut *db_trans)
+            .await?;
+        counts[&table] = json!(query(&format!(
+            "insert into main.{0} select * from restore.{0}",
+            table
+        ))
+        .execute(&mut *db_trans)
+        .await?
+        .rows_affected());
+    }
+    db_trans.commit().await?;
+    Ok(counts)
+}
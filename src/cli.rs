@@ -0,0 +1,94 @@
+use crate::config::Settings;
+use crate::types::AEState;
+use crate::{backup, dataset, maintenance, migrations, sales};
+use anyhow::{anyhow, Result};
+use serde_json::{json, to_value, Value};
+use sqlx::SqlitePool;
+use std::path::Path;
+
+pub const USAGE: &str = "用法: new_ae_server [命令] [参数]
+  serve                              启动HTTPS服务, 默认命令
+  migrate                            升级数据库结构, 其他命令要求数据库已是当前版本
+  backup [list | restore 备份文件名] 备份数据库, 列出备份, 从备份恢复
+  export 文件                        导出数据为NDJSON
+  import 文件 [merge | replace]      导入NDJSON数据, 默认merge
+  recompute-weights                  重新计算产品重量
+  recompute-sales                    按保留的订单重新计算销量
+  purge [offers | products | orders] 清理或归档过期数据, 默认全部
+  check-config                       检查配置文件
+  help                               显示本说明
+配置文件默认为当前目录的config.json5, 可用环境变量AE_CONFIG指定";
+
+//除serve和check-config外需要连接数据库的命令
+pub const COMMANDS: &[&str] = &[
+    "migrate",
+    "backup",
+    "export",
+    "import",
+    "recompute-weights",
+    "recompute-sales",
+    "purge",
+];
+
+fn usage() -> anyhow::Error {
+    anyhow!("参数错误\n{}", USAGE)
+}
+
+//执行维护命令, 不经过HTTP, 可在cron中使用
+//只有migrate升级数据库结构, 其他命令在结构版本不一致时拒绝执行, 避免运行中的旧版本服务使用新结构
+pub async fn run(state: &AEState, cmd: &str, args: &[String]) -> Result<Value> {
+    let AEState {
+        db_pool: db,
+        settings,
+    } = state;
+    let arg = |i: usize| args.get(i).map(|a| a.as_str());
+    let before = migrations::schema_version(db).await?;
+    if cmd == "migrate" {
+        if arg(0).is_some() {
+            return Err(usage());
+        }
+        migrations::migrate(db).await?;
+        return Ok(json!({ "from": before, "to": migrations::SCHEMA_VERSION }));
+    }
+    if before != migrations::SCHEMA_VERSION {
+        return Err(anyhow!(
+            "数据库结构版本为{}, 当前程序为{}, 请先执行migrate",
+            before,
+            migrations::SCHEMA_VERSION
+        ));
+    }
+    match (cmd, arg(0)) {
+        ("backup", None) => backup::create(db, settings).await,
+        ("backup", Some("list")) => Ok(to_value(backup::list(settings)?)?),
+        ("backup", Some("restore")) => match arg(1) {
+            Some(file) => backup::restore(db, settings, file).await,
+            None => Err(usage()),
+        },
+        ("export", Some(file)) => dataset::export(db, Path::new(file), None).await,
+        ("import", Some(file)) => {
+            let mode = arg(1).unwrap_or(dataset::MERGE);
+            dataset::import(db, Path::new(file), mode, None).await
+        }
+        ("recompute-weights", None) => maintenance::recompute_weights(db, settings).await,
+        ("recompute-sales", None) => sales::recompute(db, settings).await,
+        ("purge", table) => purge(db, settings, table).await,
+        _ => Err(usage()),
+    }
+}
+
+async fn purge(db: &SqlitePool, settings: &Settings, table: Option<&str>) -> Result<Value> {
+    let mut res = json!({});
+    if matches!(table, None | Some("offers")) {
+        res["offers"] = maintenance::purge_offers(db, settings).await?;
+    }
+    if matches!(table, None | Some("products")) {
+        res["products"] = maintenance::purge_products(db, settings).await?;
+    }
+    if matches!(table, None | Some("orders")) {
+        res["orders"] = maintenance::purge_orders(db, settings).await?;
+    }
+    if res.as_object().unwrap().is_empty() {
+        return Err(usage());
+    }
+    Ok(res)
+}
@@ -0,0 +1,401 @@
+use crate::{maintenance, scheduler};
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{from_value, json, to_value, Map, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::{env, fs, time::SystemTime};
+use tokio::time::{interval, Duration};
+use tracing::{error, info};
+
+//配置文件, 默认当前目录的config.json5, 可用环境变量AE_CONFIG指定
+pub fn file() -> String {
+    env::var("AE_CONFIG").unwrap_or("config.json5".to_string())
+}
+
+//检查配置文件是否修改的间隔(秒)
+const WATCH_SECONDS: u64 = 5;
+
+//服务的配置, 未配置的项使用默认值, 修改后需要重启
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub env: String,          //dev 或 prod, 默认dev
+    pub listen: String,       //默认127.0.0.1:5499
+    pub pems: Pems,           //默认pems/cert.pem, pems/key.pem
+    pub public_dir: String,   //默认public
+    pub log_level: String,    //默认debug
+    pub log_dir: String,      //prod 日志目录, 默认log
+    pub log_file: String,     //prod 日志文件, 默认ae.log
+    pub max_log_files: usize, //prod 最大日志保留数量, 默认7
+    pub tmp_dir: String,      //系统临时目录下的目录名, 默认new_ae_server
+    pub db_url: String,       //默认ae.db
+    pub settings: Settings,   //业务配置, 可热加载
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            env: "dev".to_string(),
+            listen: "127.0.0.1:5499".to_string(),
+            pems: Pems::default(),
+            public_dir: "public".to_string(),
+            log_level: "debug".to_string(),
+            log_dir: "log".to_string(),
+            log_file: "ae.log".to_string(),
+            max_log_files: 7,
+            tmp_dir: "new_ae_server".to_string(),
+            db_url: "ae.db".to_string(),
+            settings: Settings::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Pems {
+    pub cert: String,
+    pub key: String,
+}
+impl Default for Pems {
+    fn default() -> Self {
+        Self {
+            cert: "pems/cert.pem".to_string(),
+            key: "pems/key.pem".to_string(),
+        }
+    }
+}
+
+//业务配置, 字段名为配置文件中的大写名称, 各项说明见config.json5
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct Settings {
+    pub check_offer_sales_after_days: i64,         //默认90
+    pub offer_price_rate: f64,                     //默认1.5
+    pub usd2cny: f64,                              //默认3.75
+    pub not_stock_up_if_sale30_less_than: i64,     //默认5
+    pub sale2stock: f64,                           //默认0.67
+    pub need_update_weight: i64,                   //默认32
+    pub sku_barrier: i64,                          //默认16
+    pub unpublish_barrier_uv30: i64,               //默认10
+    pub analysis_before: i64,                      //默认180
+    pub weight_ratio: i64,                         //默认935
+    pub xlsx_pid_column_title: String,             //默认|商品ID|
+    pub xlsx_uv30_column_title: String,            //默认|访客数|
+    pub xlsx_sales30_column_title: String,         //默认|支付商品件数|
+    pub xlsx_date_column_title: String,            //默认|日期|统计日期|Date|
+    pub order_url_pattern: String,                 //须包含{ORDER_ID}
+    pub offer_url_pattern: String,                 //须包含{OFFER_ID}
+    pub product_url_pattern: String,               //须包含{PRODUCT_ID}
+    pub lg_order_url_pattern: String,              //须包含{LG_ORDER_ID}
+    pub retention_deleted_offers_days: i64,        //默认180
+    pub retention_deleted_products_days: i64,      //默认180
+    pub retention_orders_days: i64,                //默认180
+    pub retention_archive: BTreeMap<String, bool>, //offers/products/orders, 未配置的为true
+    pub archive_db: String,                        //默认archive.db
+    pub tracking_stuck_days: i64,                  //默认7
+    pub tracking_stuck_max_days: i64,              //默认180
+    pub lease_seconds: i64,                        //默认300
+    pub lease_backoff_seconds: i64,                //默认300
+    pub lease_backoff_max_seconds: i64,            //默认86400
+    pub offer_fail_threshold: i64,                 //默认3
+    pub supplier_price_change_penalty: f64,        //默认20
+    pub supplier_sku_change_penalty: f64,          //默认10
+    pub size_aliases: BTreeMap<String, String>,
+    pub color_aliases: BTreeMap<String, String>,
+    pub backup_dir: String,                 //默认backup
+    pub backup_max_files: usize,            //默认7, 0为不删除
+    pub schedule: BTreeMap<String, String>, //未配置的任务使用默认计划
+    pub tmp_dir: String,                    //由tmp_dir生成, 不在配置文件中设置
+    //其他项, 只供前端通过/get/cfg读取
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            check_offer_sales_after_days: 90,
+            offer_price_rate: 1.5,
+            usd2cny: 3.75,
+            not_stock_up_if_sale30_less_than: 5,
+            sale2stock: 0.67,
+            need_update_weight: 32,
+            sku_barrier: 16,
+            unpublish_barrier_uv30: 10,
+            analysis_before: 180,
+            weight_ratio: 935,
+            xlsx_pid_column_title: "|商品ID|".to_string(),
+            xlsx_uv30_column_title: "|访客数|".to_string(),
+            xlsx_sales30_column_title: "|支付商品件数|".to_string(),
+            xlsx_date_column_title: "|日期|统计日期|Date|".to_string(),
+            order_url_pattern: "https://csp.aliexpress.com/apps/order/detail?orderId={ORDER_ID}"
+                .to_string(),
+            offer_url_pattern: "https://detail.1688.com/offer/{OFFER_ID}.html".to_string(),
+            product_url_pattern: "https://csp.aliexpress.com/m_apps/aepop-product-manage/list-manage?product_id={PRODUCT_ID}".to_string(),
+            lg_order_url_pattern: "https://sg-cainiao.aliexpress.com/export/ae/logistics/order/getDetail.htm?lgOrderCode={LG_ORDER_ID}".to_string(),
+            retention_deleted_offers_days: 180,
+            retention_deleted_products_days: 180,
+            retention_orders_days: 180,
+            retention_archive: BTreeMap::new(),
+            archive_db: "archive.db".to_string(),
+            tracking_stuck_days: 7,
+            tracking_stuck_max_days: 180,
+            lease_seconds: 300,
+            lease_backoff_seconds: 300,
+            lease_backoff_max_seconds: 86400,
+            offer_fail_threshold: 3,
+            supplier_price_change_penalty: 20.0,
+            supplier_sku_change_penalty: 10.0,
+            size_aliases: BTreeMap::new(),
+            color_aliases: BTreeMap::new(),
+            backup_dir: "backup".to_string(),
+            backup_max_files: 7,
+            schedule: BTreeMap::new(),
+            tmp_dir: "tmp".to_string(),
+            extra: Map::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn tmp_path(&self) -> PathBuf {
+        env::temp_dir().join(&self.tmp_dir)
+    }
+
+    //证书只有serve使用, 不在这里检查, 命令行和热加载不需要证书
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.env != "dev" && self.env != "prod" {
+            problems.push(format!("env只能是dev或prod: {}", self.env));
+        }
+        if self.listen.parse::<SocketAddr>().is_err() {
+            problems.push(format!("listen不是有效的地址: {}", self.listen));
+        }
+        problems.extend(self.settings.validate());
+        problems
+    }
+
+    fn pem_problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for (key, pem) in [("cert", &self.pems.cert), ("key", &self.pems.key)] {
+            if !Path::new(pem).is_file() {
+                problems.push(format!("pems.{}文件不存在: {}", key, pem));
+            }
+        }
+        problems
+    }
+
+    //serve启动前检查证书文件
+    pub fn require_pems(&self) -> Result<()> {
+        let problems = self.pem_problems();
+        if !problems.is_empty() {
+            return Err(anyhow!("配置有误:\n{}", problems.join("\n")));
+        }
+        Ok(())
+    }
+}
+
+impl Settings {
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        //用于除法, 倍率或周期, 必须为正数
+        for (key, v) in [
+            ("WEIGHT_RATIO", self.weight_ratio as f64),
+            ("NEED_UPDATE_WEIGHT", self.need_update_weight as f64),
+            ("OFFER_PRICE_RATE", self.offer_price_rate),
+            ("USD2CNY", self.usd2cny),
+            (
+                "RETENTION_DELETED_OFFERS_DAYS",
+                self.retention_deleted_offers_days as f64,
+            ),
+            (
+                "RETENTION_DELETED_PRODUCTS_DAYS",
+                self.retention_deleted_products_days as f64,
+            ),
+            ("RETENTION_ORDERS_DAYS", self.retention_orders_days as f64),
+            ("TRACKING_STUCK_DAYS", self.tracking_stuck_days as f64),
+            (
+                "TRACKING_STUCK_MAX_DAYS",
+                self.tracking_stuck_max_days as f64,
+            ),
+            ("LEASE_SECONDS", self.lease_seconds as f64),
+            ("LEASE_BACKOFF_SECONDS", self.lease_backoff_seconds as f64),
+            ("OFFER_FAIL_THRESHOLD", self.offer_fail_threshold as f64),
+        ] {
+            if v <= 0.0 {
+                problems.push(format!("settings.{}必须为正数", key));
+            }
+        }
+        if self.lease_backoff_max_seconds < self.lease_backoff_seconds {
+            problems.push(
+                "settings.LEASE_BACKOFF_MAX_SECONDS不能小于LEASE_BACKOFF_SECONDS".to_string(),
+            );
+        }
+        for (key, pattern, placeholder) in [
+            ("ORDER_URL_PATTERN", &self.order_url_pattern, "{ORDER_ID}"),
+            ("OFFER_URL_PATTERN", &self.offer_url_pattern, "{OFFER_ID}"),
+            (
+                "PRODUCT_URL_PATTERN",
+                &self.product_url_pattern,
+                "{PRODUCT_ID}",
+            ),
+            (
+                "LG_ORDER_URL_PATTERN",
+                &self.lg_order_url_pattern,
+                "{LG_ORDER_ID}",
+            ),
+        ] {
+            if !pattern.contains(placeholder) {
+                problems.push(format!("settings.{}必须包含{}", key, placeholder));
+            }
+        }
+        for table in self.retention_archive.keys() {
+            if !["offers", "products", "orders"].contains(&table.as_str()) {
+                problems.push(format!("settings.RETENTION_ARCHIVE中没有该表: {}", table));
+            }
+        }
+        for task in self.schedule.keys() {
+            if !maintenance::TASKS.contains(&task.as_str()) {
+                problems.push(format!("settings.SCHEDULE中没有该任务: {}", task));
+            }
+        }
+        for (task, cron) in scheduler::schedule(self) {
+            if let Err(e) = cron {
+                problems.push(format!("settings.SCHEDULE.{}: {}", task, e));
+            }
+        }
+        problems
+    }
+}
+
+//逐项转换, 找出类型不对的项
+fn type_errors<T: DeserializeOwned>(prefix: &str, raw: &Map<String, Value>) -> Vec<String> {
+    raw.iter()
+        .filter_map(|(k, v)| {
+            from_value::<T>(json!({ k: v }))
+                .err()
+                .map(|e| format!("{}{}: {}", prefix, k, e))
+        })
+        .collect()
+}
+
+fn parse(raw: Value) -> Result<Config, Vec<String>> {
+    let mut problems = vec![];
+    match raw.as_object() {
+        None => problems.push("配置文件必须为对象".to_string()),
+        Some(top) => {
+            let mut top = top.clone();
+            match top.remove("settings") {
+                Some(Value::Object(settings)) => {
+                    problems.extend(type_errors::<Settings>("settings.", &settings))
+                }
+                Some(_) => problems.push("settings必须为对象".to_string()),
+                None => {}
+            }
+            problems.extend(type_errors::<Config>("", &top));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    let mut config: Config = from_value(raw).map_err(|e| vec![e.to_string()])?;
+    config.settings.tmp_dir = config.tmp_path().to_string_lossy().to_string();
+    Ok(config)
+}
+
+fn read() -> Result<Value> {
+    Ok(json5::from_str(&fs::read_to_string(file())?)?)
+}
+
+//读取并检查配置文件, 有问题时返回全部问题
+pub fn load() -> Result<Config> {
+    let problems = match parse(read()?) {
+        Ok(config) => {
+            let problems = config.validate();
+            if problems.is_empty() {
+                return Ok(config);
+            }
+            problems
+        }
+        Err(problems) => problems,
+    };
+    Err(anyhow!("配置有误:\n{}", problems.join("\n")))
+}
+
+//检查配置文件, 供check-config使用, 包括serve需要的证书文件, unknown为程序不使用的settings项(可能拼写错误)
+pub fn check() -> Result<Value> {
+    let (problems, unknown): (Vec<String>, Vec<String>) = match parse(read()?) {
+        Ok(config) => (
+            [config.validate(), config.pem_problems()].concat(),
+            config.settings.extra.keys().cloned().collect(),
+        ),
+        Err(problems) => (problems, vec![]),
+    };
+    Ok(json!({ "file": file(), "problems": problems, "unknown": unknown }))
+}
+
+//可热加载的settings, 每次使用时取当时的配置
+#[derive(Clone)]
+pub struct SharedSettings(Arc<RwLock<Arc<Settings>>>);
+impl SharedSettings {
+    pub fn new(settings: Settings) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(settings))))
+    }
+
+    pub fn get(&self) -> Arc<Settings> {
+        self.0.read().unwrap().clone()
+    }
+
+    //重新读取配置文件, 只替换settings, 配置有误时保留原来的, 返回改变的项
+    pub fn reload(&self) -> Result<Vec<String>> {
+        let mut settings = load()?.settings;
+        let current = self.get();
+        //临时目录在启动时已创建, 不随配置改变
+        settings.tmp_dir = current.tmp_dir.clone();
+        let old = to_value(&*current)?;
+        let new = to_value(&settings)?;
+        let mut changed: Vec<String> = new
+            .as_object()
+            .unwrap()
+            .iter()
+            .filter(|(k, v)| old.get(k.as_str()) != Some(*v))
+            .map(|(k, _)| k.clone())
+            .collect();
+        changed.extend(
+            old.as_object()
+                .unwrap()
+                .keys()
+                .filter(|k| new.get(k.as_str()).is_none())
+                .cloned(),
+        );
+        if !changed.is_empty() {
+            *self.0.write().unwrap() = Arc::new(settings);
+            info!("settings reloaded, changed: {}", changed.join(","));
+        }
+        Ok(changed)
+    }
+}
+
+fn modified() -> Option<SystemTime> {
+    fs::metadata(file()).and_then(|m| m.modified()).ok()
+}
+
+//定时检查配置文件的修改时间, 修改后重新加载settings
+pub fn watch(settings: SharedSettings) {
+    tokio::spawn(async move {
+        let mut last = modified();
+        let mut ticker = interval(Duration::from_secs(WATCH_SECONDS));
+        loop {
+            ticker.tick().await;
+            let now = modified();
+            if now == last {
+                continue;
+            }
+            last = now;
+            if let Err(e) = settings.reload() {
+                error!("settings not reloaded: {:#}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,268 @@
+use crate::jobs::JobCtx;
+use crate::models::{Offer, Order, Product};
+use crate::{links, order_items};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, from_value, json, to_string, to_value, Value};
+use sqlx::{query, query_as, QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+//导入方式
+//merge: 按offer_id/product_id/order_id更新或添加, 文件中有的记录的事件等先删除再添加
+//replace: 先清空所有相关的表
+pub const MERGE: &str = "merge";
+pub const REPLACE: &str = "replace";
+
+//记录类型, 表名, 字段: 主记录为唯一字段, 其他为所属主记录的字段
+const OFFER: &str = "offer";
+const PRODUCT: &str = "product";
+const ORDER: &str = "order";
+const KINDS: &[(&str, &str, &str)] = &[
+    (OFFER, "offers", "offer_id"),
+    ("offer_event", "offer_events", "offer_id"),
+    (PRODUCT, "products", "product_id"),
+    ("offer_product", "offer_products", "product_id"),
+    ("sku_mapping", "sku_mappings", "product_id"),
+    ("offer_switch", "offer_switches", "product_id"),
+    (ORDER, "orders", "order_id"),
+    ("shipment", "shipments", "order_id"),
+    ("tracking_event", "tracking_events", "order_id"),
+    ("order_note", "order_notes", "order_id"),
+];
+
+//每行一条记录 {"type":"offer","data":{...}}
+#[derive(Serialize, Deserialize)]
+struct Line {
+    #[serde(rename = "type")]
+    kind: String,
+    data: Value,
+}
+
+async fn columns(conn: &mut SqliteConnection, table: &str) -> Result<Vec<String>> {
+    let cols: Vec<(String,)> = query_as("select name from pragma_table_info(?) order by cid")
+        .bind(table)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(cols.into_iter().map(|c| c.0).collect())
+}
+
+//主记录用现有的模型, 其他表字段不固定, 用json_object转成json
+async fn rows(conn: &mut SqliteConnection, kind: &str, table: &str) -> Result<Vec<Value>> {
+    let sql = format!("select * from {} order by id", table);
+    let rows = match kind {
+        OFFER => query_as::<_, Offer>(&sql)
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(to_value)
+            .collect::<serde_json::Result<Vec<Value>>>()?,
+        PRODUCT => query_as::<_, Product>(&sql)
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(to_value)
+            .collect::<serde_json::Result<Vec<Value>>>()?,
+        ORDER => query_as::<_, Order>(&sql)
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(to_value)
+            .collect::<serde_json::Result<Vec<Value>>>()?,
+        _ => {
+            let json_cols = columns(conn, table)
+                .await?
+                .iter()
+                .map(|c| format!("'{0}',{0}", c))
+                .collect::<Vec<String>>()
+                .join(",");
+            let rows: Vec<(String,)> = query_as(&format!(
+                "select json_object({}) from {} order by id",
+                json_cols, table
+            ))
+            .fetch_all(&mut *conn)
+            .await?;
+            let mut res = vec![];
+            for (row,) in rows {
+                res.push(from_str(&row)?);
+            }
+            res
+        }
+    };
+    Ok(rows)
+}
+
+//导出offers/products/orders及其事件, 关联, sku对应, 包裹, 备注, 订单的商品行导入时由orders.products生成
+pub async fn export(db: &SqlitePool, file_path: &Path, job: Option<&JobCtx>) -> Result<Value> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    let mut conn = db.acquire().await?;
+    let mut counts = json!({});
+    for (i, (kind, table, _)) in KINDS.iter().enumerate() {
+        let rows = rows(&mut conn, kind, table).await?;
+        for data in rows.iter() {
+            writeln!(
+                writer,
+                "{}",
+                to_string(&json!({ "type": kind, "data": data }))?
+            )?;
+        }
+        counts[kind] = json!(rows.len());
+        if let Some(job) = job {
+            job.progress(i as i64 + 1, KINDS.len() as i64).await?;
+        }
+    }
+    writer.flush()?;
+    Ok(counts)
+}
+
+fn push_value(b: &mut QueryBuilder<Sqlite>, v: &Value) {
+    match v {
+        Value::Null => b.push_bind(None::<String>),
+        Value::Bool(x) => b.push_bind(*x),
+        Value::Number(n) if n.is_i64() => b.push_bind(n.as_i64()),
+        Value::Number(n) => b.push_bind(n.as_f64()),
+        Value::String(s) => b.push_bind(s.clone()),
+        _ => b.push_bind(v.to_string()),
+    };
+}
+
+//只使用表中有的字段, 不使用文件中的id, 主记录按key更新或添加
+async fn insert(
+    conn: &mut SqliteConnection,
+    table: &str,
+    cols: &[String],
+    data: &Value,
+    upsert_key: Option<&str>,
+) -> Result<()> {
+    let cols: Vec<&String> = cols
+        .iter()
+        .filter(|c| *c != "id" && data.get(c.as_str()).is_some())
+        .collect();
+    let mut query_builder = QueryBuilder::new(format!(
+        "insert into {} ({}) values (",
+        table,
+        cols.iter()
+            .map(|c| c.as_str())
+            .collect::<Vec<&str>>()
+            .join(",")
+    ));
+    for (i, col) in cols.iter().enumerate() {
+        if i > 0 {
+            query_builder.push(",");
+        }
+        push_value(&mut query_builder, &data[col.as_str()]);
+    }
+    query_builder.push(")");
+    if let Some(key) = upsert_key {
+        query_builder.push(format!(
+            " on conflict({}) do update set {}",
+            key,
+            cols.iter()
+                .map(|c| format!("{0}=excluded.{0}", c))
+                .collect::<Vec<String>>()
+                .join(",")
+        ));
+    }
+    query_builder.build().execute(&mut *conn).await?;
+    Ok(())
+}
+
+//在一个事务中导入, 任何一行出错都不改变数据, 重复导入同一文件结果相同
+//导入后按冗余字段补充关联并同步, 兼容没有关联记录的旧文件
+pub async fn import(
+    db: &SqlitePool,
+    file_path: &Path,
+    mode: &str,
+    job: Option<&JobCtx>,
+) -> Result<Value> {
+    if mode != MERGE && mode != REPLACE {
+        return Err(anyhow!("导入方式只能是merge或replace"));
+    }
+    let reader = BufReader::new(File::open(file_path)?);
+    //事务中不能更新任务进度, 任务表的写入会等待事务结束
+    if let Some(job) = job {
+        job.progress(0, 0).await?;
+    }
+    let mut db_trans = db.begin().await?;
+    if mode == REPLACE {
+        for (_, table, _) in KINDS.iter() {
+            query(&format!("delete from {}", table))
+                .execute(&mut *db_trans)
+                .await?;
+        }
+        query("delete from order_items")
+            .execute(&mut *db_trans)
+            .await?;
+    }
+
+    let mut table_cols: HashMap<&str, Vec<String>> = HashMap::new();
+    let mut cleared: HashSet<(&str, i64)> = HashSet::new();
+    let mut counts: BTreeMap<&str, i64> = BTreeMap::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let at = |e: serde_json::Error| anyhow!("第{}行: {}", i + 1, e);
+        let line: Line = from_str(&line).map_err(at)?;
+        let (kind, table, key) = match KINDS.iter().find(|k| k.0 == line.kind) {
+            Some(k) => *k,
+            None => {
+                return Err(anyhow!("第{}行: 未知的记录类型 {}", i + 1, line.kind));
+            }
+        };
+        //主记录用现有的模型检查格式
+        let mut order = None;
+        let data = match kind {
+            OFFER => to_value(from_value::<Offer>(line.data).map_err(at)?)?,
+            PRODUCT => to_value(from_value::<Product>(line.data).map_err(at)?)?,
+            ORDER => {
+                let o: Order = from_value(line.data).map_err(at)?;
+                let data = to_value(&o)?;
+                order = Some(o);
+                data
+            }
+            _ => line.data,
+        };
+        let id = match data[key].as_i64() {
+            Some(id) => id,
+            None => {
+                return Err(anyhow!("第{}行: 没有{}", i + 1, key));
+            }
+        };
+        if let Entry::Vacant(e) = table_cols.entry(table) {
+            e.insert(columns(&mut db_trans, table).await?);
+        }
+        let cols = &table_cols[table];
+
+        if kind == OFFER || kind == PRODUCT || kind == ORDER {
+            insert(&mut db_trans, table, cols, &data, Some(key)).await?;
+        } else {
+            //事件等没有唯一字段, 该主记录的先全部删除
+            if mode == MERGE && cleared.insert((kind, id)) {
+                query(&format!("delete from {} where {}=?", table, key))
+                    .bind(id)
+                    .execute(&mut *db_trans)
+                    .await?;
+            }
+            insert(&mut db_trans, table, cols, &data, None).await?;
+        }
+        if let Some(order) = order {
+            query("delete from order_items where order_id=?")
+                .bind(order.order_id)
+                .execute(&mut *db_trans)
+                .await?;
+            order_items::insert(&mut db_trans, &order).await?;
+        }
+        *counts.entry(kind).or_insert(0) += 1;
+    }
+    let links = links::repair_in(&mut db_trans).await?;
+    db_trans.commit().await?;
+    if let Some(job) = job {
+        let total = counts.values().sum();
+        job.progress(total, total).await?;
+    }
+    Ok(json!({ "mode": mode, "imported": counts, "links": links }))
+}
@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{query, FromRow, SqlitePool};
+use std::future::Future;
+use time::{
+    serde::rfc3339::{self as show_time, option as show_option_time},
+    OffsetDateTime,
+};
+use tracing::{error, info};
+
+//任务类型
+pub const UPLOAD_XLSX: &str = "upload_xlsx";
+pub const UPLOAD_COMMIT: &str = "upload_commit";
+pub const DISCOUNT_XLSX: &str = "discount_xlsx";
+pub const PICKING_LIST: &str = "picking_list";
+pub const RESTORE: &str = "restore";
+pub const EXPORT_DATA: &str = "export_data";
+pub const IMPORT_DATA: &str = "import_data";
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub status: String,
+    pub progress: i64,
+    pub total: i64,
+    pub message: String,
+    pub params: String,
+    pub result: String,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "show_time")]
+    pub updated_at: OffsetDateTime,
+    #[serde(with = "show_option_time")]
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+//传给任务函数, 用于报告进度
+#[derive(Clone)]
+pub struct JobCtx {
+    pub id: i64,
+    db: SqlitePool,
+}
+impl JobCtx {
+    pub async fn progress(&self, progress: i64, total: i64) -> Result<()> {
+        query("update jobs set progress=?,total=?,updated_at=? where id=?")
+            .bind(progress)
+            .bind(total)
+            .bind(OffsetDateTime::now_local()?)
+            .bind(self.id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn message(&self, message: &str) -> Result<()> {
+        query("update jobs set message=?,updated_at=? where id=?")
+            .bind(message)
+            .bind(OffsetDateTime::now_local()?)
+            .bind(self.id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn create(db: &SqlitePool, kind: &str, params: &Value) -> Result<JobCtx> {
+    let now = OffsetDateTime::now_local()?;
+    let id = query(
+        "insert into jobs (kind,status,params,created_at,updated_at) values (?,'running',?,?,?)",
+    )
+    .bind(kind)
+    .bind(params.to_string())
+    .bind(now)
+    .bind(now)
+    .execute(db)
+    .await?
+    .last_insert_rowid();
+    Ok(JobCtx { id, db: db.clone() })
+}
+
+async fn finish(db: &SqlitePool, id: i64, result: &Result<Value>) -> Result<()> {
+    let (status, message, result) = match result {
+        Ok(v) => ("done", String::new(), v.to_string()),
+        Err(e) => ("failed", format!("{:#}", e), String::new()),
+    };
+    let now = OffsetDateTime::now_local()?;
+    query("update jobs set status=?,message=?,result=?,updated_at=?,finished_at=? where id=?")
+        .bind(status)
+        .bind(message)
+        .bind(result)
+        .bind(now)
+        .bind(now)
+        .bind(id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+//创建任务并在后台执行, 立即返回任务id
+pub async fn start<F, Fut>(db: &SqlitePool, kind: &str, params: Value, f: F) -> Result<i64>
+where
+    F: FnOnce(JobCtx) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Value>> + Send + 'static,
+{
+    let ctx = create(db, kind, &params).await?;
+    let id = ctx.id;
+    let db = db.clone();
+    let kind = kind.to_string();
+    tokio::spawn(async move {
+        info!("job {} ({}) started", id, kind);
+        //任务panic时也要记录失败
+        let result = match tokio::spawn(f(ctx)).await {
+            Ok(result) => result,
+            Err(e) => Err(anyhow!("任务异常退出: {}", e)),
+        };
+        if let Err(e) = &result {
+            error!("job {} ({}) failed: {:#}", id, kind, e);
+        } else {
+            info!("job {} ({}) done", id, kind);
+        }
+        if let Err(e) = finish(&db, id, &result).await {
+            error!("job {} ({}) status not saved: {:#}", id, kind, e);
+        }
+    });
+    Ok(id)
+}
+
+//服务器重启时, 上次未完成的任务已中断
+pub async fn fail_interrupted(db: &SqlitePool) -> Result<()> {
+    let now = OffsetDateTime::now_local()?;
+    let affected_rows = query("update jobs set status='failed',message='服务器重启, 任务中断',updated_at=?,finished_at=? where status='running'")
+        .bind(now)
+        .bind(now)
+        .execute(db)
+        .await?
+        .rows_affected();
+    if affected_rows > 0 {
+        info!("{} interrupted jobs marked as failed", affected_rows);
+    }
+    Ok(())
+}
+
+pub fn file_result(file: &str) -> Value {
+    json!({ "file": file })
+}
@@ -0,0 +1,141 @@
+use crate::config::Settings;
+use anyhow::Result;
+use serde::Deserialize;
+use sqlx::{query, query_as, SqlitePool};
+use std::cmp::min;
+use time::{Duration, OffsetDateTime};
+
+//租用类型
+pub const OFFER: &str = "offer";
+//物流包裹(shipments.id), 统计重量
+pub const SHIPMENT: &str = "shipment";
+
+//每次最多租用数量
+pub const MAX_BATCH: i64 = 50;
+
+//租约时长及失败后的退避时间(秒), 退避时间每次失败翻倍
+pub struct LeaseConf {
+    lease: i64,
+    backoff: i64,
+    backoff_max: i64,
+}
+impl LeaseConf {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            lease: settings.lease_seconds,
+            backoff: settings.lease_backoff_seconds,
+            backoff_max: settings.lease_backoff_max_seconds,
+        }
+    }
+
+    fn backoff(&self, attempts: i64) -> Duration {
+        let times = 1i64 << min(attempts.max(1) - 1, 20);
+        Duration::seconds(min(self.backoff.saturating_mul(times), self.backoff_max))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LeaseFail {
+    pub id: i64,
+    #[serde(default)]
+    pub error: String,
+}
+
+//租约时间只保留到秒, 保证按字符串比较时顺序正确
+pub fn now() -> Result<OffsetDateTime> {
+    Ok(OffsetDateTime::now_local()?.replace_nanosecond(0)?)
+}
+
+//租用一项, 已被其他人租用时返回None, 成功返回到期时间
+//到期未完成视为失败, 到期后再等待退避时间才能再次租用
+pub async fn claim(
+    db: &SqlitePool,
+    conf: &LeaseConf,
+    kind: &str,
+    item_id: i64,
+    worker: &str,
+) -> Result<Option<OffsetDateTime>> {
+    let now = now()?;
+    let expires_at = now + Duration::seconds(conf.lease);
+    let lease: Option<(i64,)> = query_as("select attempts from leases where kind=? and item_id=?")
+        .bind(kind)
+        .bind(item_id)
+        .fetch_optional(db)
+        .await?;
+    let affected_rows = match lease {
+        None => query("insert into leases (kind,item_id,worker,attempts,leased_at,expires_at,available_at) values (?,?,?,1,?,?,?) on conflict(kind,item_id) do nothing")
+            .bind(kind)
+            .bind(item_id)
+            .bind(worker)
+            .bind(now)
+            .bind(expires_at)
+            .bind(expires_at + conf.backoff(1))
+            .execute(db)
+            .await?
+            .rows_affected(),
+        //用attempts做版本号, 同时租用时只有一个能成功
+        Some((attempts,)) => query("update leases set worker=?,attempts=?,leased_at=?,expires_at=?,available_at=? where kind=? and item_id=? and attempts=? and available_at<=?")
+            .bind(worker)
+            .bind(attempts + 1)
+            .bind(now)
+            .bind(expires_at)
+            .bind(expires_at + conf.backoff(attempts + 1))
+            .bind(kind)
+            .bind(item_id)
+            .bind(attempts)
+            .bind(now)
+            .execute(db)
+            .await?
+            .rows_affected(),
+    };
+    if affected_rows > 0 {
+        Ok(Some(expires_at))
+    } else {
+        Ok(None)
+    }
+}
+
+//报告失败, 提前结束租约, 退避后重试
+//租约已结束(重复报告或已过期)时返回false
+pub async fn fail(
+    db: &SqlitePool,
+    conf: &LeaseConf,
+    kind: &str,
+    item_id: i64,
+    error: &str,
+) -> Result<bool> {
+    let lease: Option<(i64,)> = query_as("select attempts from leases where kind=? and item_id=?")
+        .bind(kind)
+        .bind(item_id)
+        .fetch_optional(db)
+        .await?;
+    let attempts = match lease {
+        Some((attempts,)) => attempts,
+        None => {
+            return Ok(false);
+        }
+    };
+    let now = now()?;
+    let affected_rows = query("update leases set last_error=?,expires_at=?,available_at=? where kind=? and item_id=? and attempts=? and expires_at>?")
+        .bind(error)
+        .bind(now)
+        .bind(now + conf.backoff(attempts))
+        .bind(kind)
+        .bind(item_id)
+        .bind(attempts)
+        .bind(now)
+        .execute(db)
+        .await?
+        .rows_affected();
+    Ok(affected_rows > 0)
+}
+
+//完成后删除租约, 下次需要更新时从头计算
+pub async fn release(db: &SqlitePool, kind: &str, item_id: i64) -> Result<()> {
+    query("delete from leases where kind=? and item_id=?")
+        .bind(kind)
+        .bind(item_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
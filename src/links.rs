@@ -0,0 +1,249 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{query, query_as, FromRow, SqliteConnection, SqlitePool};
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+
+//offer和product的关联, 一个product可以有多个备用offer, 一个offer也可以供多个product
+//products.offer_id(主offer) 和 offers.product_id(优先主offer关联的, 其次最早关联的) 只是冗余, 由这里同步
+#[derive(Serialize, FromRow)]
+pub struct Link {
+    pub id: i64,
+    pub offer_id: i64,
+    pub product_id: i64,
+    pub is_primary: i64,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+}
+
+//products.offer_id 应有的值
+const PRODUCT_OFFER_ID: &str = "coalesce((select l.offer_id from offer_products l where l.product_id=products.product_id and l.is_primary=1),0)";
+//products.offer_unavailable 应有的值, 跟随主offer
+const OFFER_UNAVAILABLE: &str = "coalesce((select o.unavailable_at is not null from offers o where o.offer_id=products.offer_id),0)";
+//offers.product_id 应有的值
+const OFFER_PRODUCT_ID: &str = "coalesce((select l.product_id from offer_products l where l.offer_id=offers.offer_id order by l.is_primary desc, l.id asc limit 1),0)";
+
+async fn sync_product(conn: &mut SqliteConnection, product_id: i64) -> Result<()> {
+    query(&format!(
+        "update products set offer_id={PRODUCT_OFFER_ID} where product_id=?"
+    ))
+    .bind(product_id)
+    .execute(&mut *conn)
+    .await?;
+    query(&format!(
+        "update products set offer_unavailable={OFFER_UNAVAILABLE} where product_id=?"
+    ))
+    .bind(product_id)
+    .execute(&mut *conn)
+    .await?;
+    query(&format!("update offers set product_id={OFFER_PRODUCT_ID} where offer_id in (select offer_id from offer_products where product_id=?)"))
+        .bind(product_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn sync_offer(conn: &mut SqliteConnection, offer_id: i64) -> Result<()> {
+    query(&format!(
+        "update offers set product_id={OFFER_PRODUCT_ID} where offer_id=?"
+    ))
+    .bind(offer_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn product_links(db: &SqlitePool, product_id: i64) -> Result<Vec<Link>> {
+    Ok(
+        query_as(
+            "select * from offer_products where product_id=? order by is_primary desc, id asc",
+        )
+        .bind(product_id)
+        .fetch_all(db)
+        .await?,
+    )
+}
+
+pub async fn offer_links(db: &SqlitePool, offer_id: i64) -> Result<Vec<Link>> {
+    Ok(
+        query_as("select * from offer_products where offer_id=? order by id asc")
+            .bind(offer_id)
+            .fetch_all(db)
+            .await?,
+    )
+}
+
+//添加关联, primary为true时设为主offer, 原主offer变为备用; 商品还没有主offer时也设为主offer
+pub async fn link(
+    conn: &mut SqliteConnection,
+    offer_id: i64,
+    product_id: i64,
+    primary: bool,
+) -> Result<bool> {
+    let mut changed = query("insert into offer_products (offer_id,product_id,is_primary,created_at) values (?,?,0,?) on conflict(offer_id,product_id) do nothing")
+        .bind(offer_id)
+        .bind(product_id)
+        .bind(OffsetDateTime::now_local()?)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+        > 0;
+    let has_primary: Option<(i64,)> =
+        query_as("select offer_id from offer_products where product_id=? and is_primary=1")
+            .bind(product_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+    if primary || has_primary.is_none() {
+        changed |= set_primary(conn, product_id, offer_id).await?;
+    }
+    sync_product(conn, product_id).await?;
+    Ok(changed)
+}
+
+//设置主offer, offer_id为0时取消主offer, 关联仍保留为备用
+pub async fn set_primary(
+    conn: &mut SqliteConnection,
+    product_id: i64,
+    offer_id: i64,
+) -> Result<bool> {
+    let changed = query("update offer_products set is_primary=(offer_id=?) where product_id=? and is_primary!=(offer_id=?)")
+        .bind(offer_id)
+        .bind(product_id)
+        .bind(offer_id)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+        > 0;
+    sync_product(conn, product_id).await?;
+    Ok(changed)
+}
+
+//删除关联, 删除的是主offer时最早的备用offer成为主offer
+pub async fn unlink(conn: &mut SqliteConnection, offer_id: i64, product_id: i64) -> Result<bool> {
+    let removed: Option<(i64,)> = query_as(
+        "delete from offer_products where offer_id=? and product_id=? returning is_primary",
+    )
+    .bind(offer_id)
+    .bind(product_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some((1,)) = removed {
+        query("update offer_products set is_primary=1 where id=(select id from offer_products where product_id=? order by id asc limit 1)")
+            .bind(product_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    sync_offer(conn, offer_id).await?;
+    sync_product(conn, product_id).await?;
+    Ok(removed.is_some())
+}
+
+//删除offer的所有关联
+pub async fn unlink_offer(conn: &mut SqliteConnection, offer_id: i64) -> Result<bool> {
+    let product_ids: Vec<(i64,)> =
+        query_as("select product_id from offer_products where offer_id=?")
+            .bind(offer_id)
+            .fetch_all(&mut *conn)
+            .await?;
+    for (product_id,) in product_ids.iter() {
+        unlink(conn, offer_id, *product_id).await?;
+    }
+    Ok(!product_ids.is_empty())
+}
+
+//按关联重新同步所有冗余字段, 返回更新的product和offer数
+pub async fn sync_all(conn: &mut SqliteConnection) -> Result<(u64, u64)> {
+    let products = query(&format!(
+        "update products set offer_id={PRODUCT_OFFER_ID} where offer_id!={PRODUCT_OFFER_ID}"
+    ))
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    query(&format!(
+        "update products set offer_unavailable={OFFER_UNAVAILABLE}"
+    ))
+    .execute(&mut *conn)
+    .await?;
+    let offers = query(&format!(
+        "update offers set product_id={OFFER_PRODUCT_ID} where product_id!={OFFER_PRODUCT_ID}"
+    ))
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    Ok((products, offers))
+}
+
+//检查冗余字段和关联是否一致, 以及关联到不存在的offer/product
+pub async fn check(db: &SqlitePool) -> Result<Value> {
+    let products: Vec<(i64, i64, i64)> = query_as(&format!(
+        "select product_id, offer_id, {PRODUCT_OFFER_ID} as expected from products where offer_id!=expected"
+    ))
+    .fetch_all(db)
+    .await?;
+    let offers: Vec<(i64, i64, i64)> = query_as(&format!(
+        "select offer_id, product_id, {OFFER_PRODUCT_ID} as expected from offers where product_id!=expected"
+    ))
+    .fetch_all(db)
+    .await?;
+    let multi_primary: Vec<(i64,)> = query_as(
+        "select product_id from offer_products group by product_id having sum(is_primary)>1",
+    )
+    .fetch_all(db)
+    .await?;
+    let missing_offers: Vec<Link> = query_as(
+        "select * from offer_products l where not exists (select id from offers o where o.offer_id=l.offer_id)",
+    )
+    .fetch_all(db)
+    .await?;
+    let missing_products: Vec<Link> = query_as(
+        "select * from offer_products l where not exists (select id from products p where p.product_id=l.product_id)",
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(json!({
+        "ok": products.is_empty() && offers.is_empty() && multi_primary.is_empty(),
+        "products": products.iter().map(|p| json!({"product_id": p.0, "offer_id": p.1, "expected": p.2})).collect::<Vec<Value>>(),
+        "offers": offers.iter().map(|o| json!({"offer_id": o.0, "product_id": o.1, "expected": o.2})).collect::<Vec<Value>>(),
+        "multi_primary": multi_primary.iter().map(|p| p.0).collect::<Vec<i64>>(),
+        "missing_offers": missing_offers,
+        "missing_products": missing_products,
+    }))
+}
+
+//修复: 冗余字段中有而关联中没有的补充为关联(products.offer_id为主offer), 再按关联重新同步冗余字段
+//关联到不存在的offer/product的不处理, 只在检查结果中列出
+pub async fn repair(db: &SqlitePool) -> Result<Value> {
+    let mut db_trans = db.begin().await?;
+    let res = repair_in(&mut db_trans).await?;
+    db_trans.commit().await?;
+    Ok(res)
+}
+
+//同repair, 在调用方的事务中执行
+pub async fn repair_in(conn: &mut SqliteConnection) -> Result<Value> {
+    let now = OffsetDateTime::now_local()?;
+    let added_primary = query("insert into offer_products (offer_id,product_id,is_primary,created_at) select offer_id, product_id, 0, ? from products where offer_id>0 on conflict(offer_id,product_id) do nothing")
+        .bind(now)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+    query("update offer_products set is_primary=(offer_id=(select p.offer_id from products p where p.product_id=offer_products.product_id)) where product_id in (select product_id from products where offer_id>0)")
+        .execute(&mut *conn)
+        .await?;
+    let added_backup = query("insert into offer_products (offer_id,product_id,is_primary,created_at) select offer_id, product_id, 0, ? from offers where product_id>0 on conflict(offer_id,product_id) do nothing")
+        .bind(now)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+    //有多个主offer的, 只保留最早的
+    query("update offer_products set is_primary=0 where is_primary=1 and id not in (select min(id) from offer_products where is_primary=1 group by product_id)")
+        .execute(&mut *conn)
+        .await?;
+    let (products, offers) = sync_all(&mut *conn).await?;
+    Ok(json!({
+        "added_primary": added_primary,
+        "added_backup": added_backup,
+        "products": products,
+        "offers": offers,
+    }))
+}
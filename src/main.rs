@@ -1,12 +1,11 @@
 use anyhow::Result;
 use axum::{extract::Path, routing::get};
 use axum_server::{tls_rustls::RustlsConfig, Handle};
-use json5;
-use serde_json::Value;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-use std::env;
-use std::fs;
+use config::{Config, SharedSettings};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::{env, fs, process};
 use tokio::{signal, sync::mpsc, time::Duration};
 use tower::ServiceBuilder;
 use tower_http::{
@@ -18,39 +17,119 @@ use tower_http::{
 use tracing::{debug, info};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::fmt::{self, time::LocalTime};
-use types::AEState;
+use types::{AEState, ServerState};
 
+mod analytics;
+mod archive;
+mod backup;
+mod cli;
+mod config;
+mod dataset;
+mod jobs;
+mod leases;
+mod links;
+mod maintenance;
+mod migrations;
 mod models;
+mod normalize;
+mod order_items;
+mod order_notes;
+mod picking;
 mod routes;
+mod sales;
+mod scheduler;
+mod shipments;
+mod skus;
+mod sourcing;
+mod suppliers;
+mod tracking;
 mod types;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut config: Value = json5::from_str(&fs::read_to_string("config.json5")?)?;
+    //不带命令时启动服务, 其他命令见cli::USAGE
+    let args: Vec<String> = env::args().skip(1).collect();
+    let cmd = args.first().map(|a| a.as_str()).unwrap_or("serve");
+    if cmd == "help" {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if cmd != "serve" && cmd != "check-config" && !cli::COMMANDS.contains(&cmd) {
+        eprintln!("{}", cli::USAGE);
+        process::exit(2);
+    }
+    if cmd == "check-config" {
+        let report = config::check()?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if report["problems"].as_array().is_some_and(|p| !p.is_empty()) {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    let config = config::load()?;
+    if cmd == "serve" {
+        config.require_pems()?;
+    }
+
+    init_logger(&config, cmd == "serve")?;
+    let db_pool = connect(&config).await?;
+    if cmd == "serve" {
+        return serve(config, db_pool).await;
+    }
 
-    let log_level = &config["log_level"].as_str().unwrap_or("debug").to_string();
+    //服务可能正在运行, 不能清空临时目录
+    fs::create_dir_all(config.tmp_path())?;
+    let state = AEState {
+        db_pool,
+        settings: Arc::new(config.settings),
+    };
+    let res = cli::run(&state, cmd, &args[1..]).await;
+    state.db_pool.close().await;
+    println!("{}", serde_json::to_string_pretty(&res?)?);
+    Ok(())
+}
+
+//prod输出到日志文件, 否则服务输出到stdout, 命令行输出到stderr, stdout只输出结果
+fn init_logger(config: &Config, serve: bool) -> Result<()> {
+    let log_level = &config.log_level;
     let logger = fmt::fmt()
         .with_env_filter(format!(
             "new_ae_server={0},tower_http={0},axum::rejection={0},sqlx={0}",
             log_level
         ))
         .with_timer(LocalTime::rfc_3339());
-    if config["env"].as_str().unwrap_or("dev") == "prod" {
+    if config.env == "prod" {
         logger
             .with_writer(
                 RollingFileAppender::builder()
                     .rotation(Rotation::DAILY)
-                    .filename_prefix(config["log_file"].as_str().unwrap_or("ae.log"))
-                    .max_log_files(config["max_log_files"].as_u64().unwrap_or(7) as usize)
-                    .build(config["log_dir"].as_str().unwrap_or("log"))?,
+                    .filename_prefix(&config.log_file)
+                    .max_log_files(config.max_log_files)
+                    .build(&config.log_dir)?,
             )
             .with_ansi(false)
             .init();
-    } else {
+    } else if serve {
         logger.with_writer(std::io::stdout).init();
+    } else {
+        logger.with_writer(std::io::stderr).init();
     }
+    Ok(())
+}
+
+async fn connect(config: &Config) -> Result<SqlitePool> {
+    Ok(SqlitePoolOptions::new()
+        .max_connections(4)
+        .connect_with(
+            SqliteConnectOptions::from_str(&config.db_url)?
+                .create_if_missing(true)
+                .with_regexp(),
+        )
+        .await?)
+}
 
-    let tmp_dir = env::temp_dir().join(config["tmp_dir"].as_str().unwrap_or("new_ae_server"));
+async fn serve(config: Config, db_pool: SqlitePool) -> Result<()> {
+    let tmp_dir = config.tmp_path();
     debug!("tmp directory path: {}", &tmp_dir.display());
     if tmp_dir.exists() {
         debug!("remove tmp directory");
@@ -58,31 +137,22 @@ async fn main() -> Result<()> {
     }
     debug!("recreate tmp directory");
     fs::create_dir(&tmp_dir)?;
-    config["settings"]["TMP_DIR"] = tmp_dir.to_str().unwrap().into();
 
-    let tls_config = RustlsConfig::from_pem_file(
-        config["pems"]["cert"].as_str().unwrap_or("pems/cert.pem"),
-        config["pems"]["key"].as_str().unwrap_or("pems/key.pem"),
-    )
-    .await?;
+    let tls_config = RustlsConfig::from_pem_file(&config.pems.cert, &config.pems.key).await?;
 
-    let listen = config["listen"].as_str().unwrap_or("127.0.0.1:5499");
-    let db_pool = SqlitePoolOptions::new()
-        .max_connections(4)
-        .connect_with(
-            SqliteConnectOptions::from_str(config["db_url"].as_str().unwrap_or("ae.db"))?
-                .with_regexp(),
-        )
-        .await?;
-    let state = AEState {
+    let listen = config.listen.as_str();
+    migrations::migrate(&db_pool).await?;
+    jobs::fail_interrupted(&db_pool).await?;
+    //settings可热加载, 其他配置修改后需要重启
+    let settings = SharedSettings::new(config.settings);
+    config::watch(settings.clone());
+    scheduler::spawn(db_pool.clone(), settings.clone());
+    let state = ServerState {
         db_pool: db_pool.clone(),
-        settings: config["settings"].clone(),
+        settings,
     };
     let (tx, mut rx) = mpsc::channel::<u64>(1);
-    let public_dir = config["public_dir"]
-        .as_str()
-        .unwrap_or("public")
-        .trim_matches('/');
+    let public_dir = config.public_dir.trim_matches('/');
     let spa_fallback = ServeDir::new(public_dir)
         .not_found_service(ServeFile::new(public_dir.to_string() + "/index.html"));
     let app = routes::router(state)
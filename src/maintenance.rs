@@ -0,0 +1,258 @@
+use crate::config::Settings;
+use crate::jobs::JobCtx;
+use crate::{
+    analytics, archive, backup, links, order_items, order_notes, sales, shipments, sourcing,
+    suppliers, tracking,
+};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use sqlx::{query, query_as, Connection, SqliteConnection, SqlitePool};
+use std::collections::HashSet;
+use time::{Date, Duration, OffsetDateTime};
+
+//定时任务, 名称同时作为任务类型
+pub const PURGE_OFFERS: &str = "purge_offers";
+pub const PURGE_PRODUCTS: &str = "purge_products";
+pub const PURGE_ORDERS: &str = "purge_orders";
+pub const DAILY_SNAPSHOT: &str = "daily_snapshot";
+pub const RECOMPUTE_WEIGHTS: &str = "recompute_weights";
+pub const RECOMPUTE_TRAFFIC: &str = "recompute_traffic";
+pub const BACKUP: &str = "backup";
+pub const REFRESH_SUPPLIERS: &str = "refresh_suppliers";
+pub const RECOMPUTE_SALES: &str = "recompute_sales";
+pub const TASKS: &[&str] = &[
+    PURGE_OFFERS,
+    PURGE_PRODUCTS,
+    PURGE_ORDERS,
+    DAILY_SNAPSHOT,
+    RECOMPUTE_WEIGHTS,
+    RECOMPUTE_TRAFFIC,
+    BACKUP,
+    REFRESH_SUPPLIERS,
+    RECOMPUTE_SALES,
+];
+
+pub async fn run(db: &SqlitePool, settings: &Settings, task: &str, job: &JobCtx) -> Result<Value> {
+    match task {
+        PURGE_OFFERS => purge_offers(db, settings).await,
+        PURGE_PRODUCTS => purge_products(db, settings).await,
+        PURGE_ORDERS => purge_orders(db, settings).await,
+        DAILY_SNAPSHOT => daily_snapshot(db).await,
+        RECOMPUTE_WEIGHTS => recompute_weights(db, settings).await,
+        RECOMPUTE_TRAFFIC => analytics::recompute_traffic(db, settings, job).await,
+        BACKUP => backup::create(db, settings).await,
+        REFRESH_SUPPLIERS => suppliers::refresh(db, settings).await,
+        RECOMPUTE_SALES => sales::recompute(db, settings).await,
+        _ => Err(anyhow!("没有该任务: {}", task)),
+    }
+}
+
+//废弃超过保留天数的offer连同关联/sku对应/事件移到归档数据库, RETENTION_ARCHIVE.offers为false时直接删除
+//作为主offer的商品先重新选择主offer, 没有可用备用offer时同links::unlink, 最早的备用offer成为主offer
+pub async fn purge_offers(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let days = settings.retention_deleted_offers_days;
+    let archived = archive::enabled(settings, "offers");
+    let before = OffsetDateTime::now_local()?.date() - Duration::days(days);
+    let mut conn = archive::open(db, settings, archived).await?;
+    let res = purge_offers_on(&mut conn, before, archived).await;
+    archive::close(&mut conn, archived).await?;
+    let (moved, reselected) = res?;
+    let mut res = json!({ "archived": archived, "days": days, "reselected": reselected });
+    for (table, n) in archive::OFFER_TABLES.iter().zip(moved.iter()) {
+        res[*table] = json!(n);
+    }
+    res["deleted"] = json!(moved[archive::OFFER_TABLES.len()]);
+    Ok(res)
+}
+
+async fn purge_offers_on(
+    conn: &mut SqliteConnection,
+    before: Date,
+    archived: bool,
+) -> Result<(Vec<u64>, Vec<i64>)> {
+    let mut db_trans = conn.begin().await?;
+    let product_ids: Vec<(i64,)> = query_as("select distinct l.product_id from offer_products l join offers o on o.offer_id=l.offer_id where l.is_primary=1 and o.deleted_at is not null and o.deleted_at < ?")
+        .bind(before)
+        .fetch_all(&mut *db_trans)
+        .await?;
+    let mut reselected = vec![];
+    for (product_id,) in product_ids.iter().copied() {
+        let detail = json!({ "purged": true });
+        if sourcing::reselect(
+            &mut db_trans,
+            product_id,
+            sourcing::DELETED,
+            &detail,
+            &HashSet::new(),
+        )
+        .await?
+        .is_some()
+        {
+            reselected.push(product_id);
+        }
+    }
+    let mut tables: Vec<(&str, String)> = archive::OFFER_TABLES
+        .iter()
+        .map(|t| {
+            (
+                *t,
+                "offer_id in (select offer_id from main.offers where deleted_at is not null and deleted_at < ?1)".to_string(),
+            )
+        })
+        .collect();
+    tables.push((
+        "offers",
+        "deleted_at is not null and deleted_at < ?1".to_string(),
+    ));
+    let moved = archive::move_tables(&mut db_trans, &[], &tables, before, archived).await?;
+    for (product_id,) in product_ids {
+        query("update offer_products set is_primary=1 where id=(select id from offer_products where product_id=?1 order by id asc limit 1) and not exists (select id from offer_products where product_id=?1 and is_primary=1)")
+            .bind(product_id)
+            .execute(&mut *db_trans)
+            .await?;
+    }
+    links::sync_all(&mut db_trans).await?;
+    db_trans.commit().await?;
+    Ok((moved, reselected))
+}
+
+//废弃超过保留天数的product连同关联/sku对应/切换记录移到归档数据库, 同purge_offers
+pub async fn purge_products(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let days = settings.retention_deleted_products_days;
+    let archived = archive::enabled(settings, "products");
+    let before = OffsetDateTime::now_local()?.date() - Duration::days(days);
+    let mut conn = archive::open(db, settings, archived).await?;
+    let res = purge_products_on(&mut conn, before, archived).await;
+    archive::close(&mut conn, archived).await?;
+    let moved = res?;
+    let mut res = json!({ "archived": archived, "days": days });
+    for (table, n) in archive::PRODUCT_TABLES.iter().zip(moved.iter()) {
+        res[*table] = json!(n);
+    }
+    res["deleted"] = json!(moved[archive::PRODUCT_TABLES.len()]);
+    Ok(res)
+}
+
+async fn purge_products_on(
+    conn: &mut SqliteConnection,
+    before: Date,
+    archived: bool,
+) -> Result<Vec<u64>> {
+    let mut db_trans = conn.begin().await?;
+    let mut tables: Vec<(&str, String)> = archive::PRODUCT_TABLES
+        .iter()
+        .map(|t| {
+            (
+                *t,
+                "product_id in (select product_id from main.products where deleted_at is not null and deleted_at < ?1)".to_string(),
+            )
+        })
+        .collect();
+    tables.push((
+        "products",
+        "deleted_at is not null and deleted_at < ?1".to_string(),
+    ));
+    let moved = archive::move_tables(&mut db_trans, &[], &tables, before, archived).await?;
+    //关联到已删除商品的offer重新同步offers.product_id
+    links::sync_all(&mut db_trans).await?;
+    db_trans.commit().await?;
+    Ok(moved)
+}
+
+//创建超过保留天数的订单连同商品行/包裹/物流事件/备注移到归档数据库
+//销量先按天汇总到archived_sales, 直接删除时也保留
+pub async fn purge_orders(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let days = settings.retention_orders_days;
+    let archived = archive::enabled(settings, "orders");
+    let mut tables: Vec<(&str, String)> = archive::ORDER_TABLES
+        .iter()
+        .map(|t| {
+            (
+                *t,
+                "order_id in (select order_id from main.orders where created_at < ?1)".to_string(),
+            )
+        })
+        .collect();
+    tables.push(("orders", "created_at < ?1".to_string()));
+    let moved = archive::purge(
+        db,
+        settings,
+        &[sales::ARCHIVE_SQL],
+        &tables,
+        OffsetDateTime::now_local()?.date() - Duration::days(days),
+        archived,
+    )
+    .await?;
+    let mut res = json!({ "archived": archived, "days": days });
+    for ((table, _), n) in tables.iter().zip(moved) {
+        res[if *table == "orders" {
+            "deleted"
+        } else {
+            *table
+        }] = json!(n);
+    }
+    //之前直接删除订单时留下的数据
+    res["orphans"] = json!(
+        order_items::purge(db).await?
+            + shipments::purge(db).await?
+            + tracking::purge(db).await?
+            + order_notes::purge(db).await?
+    );
+    Ok(res)
+}
+
+//记录当天的汇总数据, 同一天重复执行时覆盖
+pub async fn daily_snapshot(db: &SqlitePool) -> Result<Value> {
+    let now = OffsetDateTime::now_local()?;
+    let today = now.date();
+    let offers: (i64, i64, i64, i64) = query_as("select count(id), coalesce(sum(pending=-1),0), coalesce(sum(pending=-2),0), coalesce(sum(deleted_at is not null),0) from offers")
+        .fetch_one(db)
+        .await?;
+    let products: (i64, i64, i64, i64, i64, i64) = query_as("select count(id), coalesce(sum(pending=-1),0), coalesce(sum(pending=-2),0), coalesce(sum(deleted_at is not null),0), coalesce(sum(stock_count),0), coalesce(sum(sale_count),0) from products")
+        .fetch_one(db)
+        .await?;
+    let orders: (i64, i64, i64) = query_as("select count(id), coalesce(sum(created_at >= ?),0), coalesce(sum(weight=0 and lg_order_id is not null),0) from orders")
+        .bind(today)
+        .fetch_one(db)
+        .await?;
+    let data = json!({
+        "offers": {
+            "total": offers.0,
+            "pending": offers.1,
+            "unpublished": offers.2,
+            "deleted": offers.3,
+        },
+        "products": {
+            "total": products.0,
+            "pending_weight": products.1,
+            "pending_delist": products.2,
+            "deleted": products.3,
+            "stock_count": products.4,
+            "sale_count": products.5,
+        },
+        "orders": {
+            "total": orders.0,
+            "today": orders.1,
+            "no_weight": orders.2,
+        },
+    });
+    query("insert into daily_snapshots (date,data,created_at) values (?,?,?) on conflict(date) do update set data=excluded.data,created_at=excluded.created_at")
+        .bind(today)
+        .bind(data.to_string())
+        .bind(now)
+        .execute(db)
+        .await?;
+    Ok(data)
+}
+
+//按已卖出总重量重新计算建议重量, WEIGHT_RATIO修改后使用
+pub async fn recompute_weights(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let weight_ratio = settings.weight_ratio;
+    let updated = query("update products set weight=(sale_weight/weight_cal_count)*1000/? where weight_cal_count>0 and weight!=(sale_weight/weight_cal_count)*1000/?")
+        .bind(weight_ratio)
+        .bind(weight_ratio)
+        .execute(db)
+        .await?
+        .rows_affected();
+    Ok(json!({ "updated": updated }))
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use sqlx::{query, query_as, Executor, SqlitePool};
+use tracing::info;
+
+//初始表结构, 对应版本0
+const TABLES: &str = include_str!("tables.sql");
+
+//按顺序执行, 执行后 PRAGMA user_version 为已执行的数量, 只能在末尾追加
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/001_jobs.sql"),
+    include_str!("migrations/002_daily_snapshots.sql"),
+    include_str!("migrations/003_leases.sql"),
+    include_str!("migrations/004_offer_failures.sql"),
+    include_str!("migrations/005_offer_products.sql"),
+    include_str!("migrations/006_offer_switches.sql"),
+    include_str!("migrations/007_suppliers.sql"),
+    include_str!("migrations/008_sku_mappings.sql"),
+    include_str!("migrations/009_stock_locations.sql"),
+    include_str!("migrations/010_order_items.sql"),
+    include_str!("migrations/011_order_item_prices.sql"),
+    include_str!("migrations/012_tracking.sql"),
+    include_str!("migrations/013_shipments.sql"),
+    include_str!("migrations/014_order_notes.sql"),
+    include_str!("migrations/015_archived_sales.sql"),
+];
+
+//当前程序的数据库结构版本
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+pub async fn schema_version(db: &SqlitePool) -> Result<i64> {
+    let version: (i64,) = query_as("PRAGMA user_version").fetch_one(db).await?;
+    Ok(version.0)
+}
+
+//空数据库先创建初始表, 再执行未执行过的迁移
+pub async fn migrate(db: &SqlitePool) -> Result<()> {
+    let tables: Option<(String,)> =
+        query_as("select name from sqlite_master where type='table' and name='offers'")
+            .fetch_optional(db)
+            .await?;
+    if tables.is_none() {
+        info!("empty database, create tables");
+        db.execute(TABLES).await?;
+    }
+
+    let version = schema_version(db).await?;
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("migrate database to version {}", i + 1);
+        let mut db_trans = db.begin().await?;
+        (&mut *db_trans).execute(*sql).await?;
+        query(&format!("PRAGMA user_version = {}", i + 1))
+            .execute(&mut *db_trans)
+            .await?;
+        db_trans.commit().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+CREATE TABLE jobs(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    kind VARCHAR(32) NOT NULL DEFAULT '', -- 任务类型
+    status VARCHAR(16) NOT NULL DEFAULT 'running', -- running, done, failed
+    progress INTEGER NOT NULL DEFAULT 0, -- 已处理数量
+    total INTEGER NOT NULL DEFAULT 0, -- 总数量, 0为未知
+    message TEXT NOT NULL DEFAULT '', -- 进度说明或错误信息
+    params TEXT NOT NULL DEFAULT '{}', -- 任务参数, json格式
+    result TEXT NOT NULL DEFAULT '', -- 任务结果, json格式
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 创建时间
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 进度更新时间
+    finished_at TIMESTAMP -- 完成时间
+);
+CREATE INDEX jobs_kind on jobs (kind);
+CREATE INDEX jobs_status on jobs (status);
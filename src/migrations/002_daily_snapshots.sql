@@ -0,0 +1,9 @@
+CREATE TABLE daily_snapshots(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    date DATE NOT NULL, -- 快照日期
+    data TEXT NOT NULL DEFAULT '{}', -- 当天的统计数据, json格式
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 创建时间
+);
+CREATE UNIQUE INDEX daily_snapshots_date on daily_snapshots (date);
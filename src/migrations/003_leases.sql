@@ -0,0 +1,15 @@
+CREATE TABLE leases(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    kind VARCHAR(16) NOT NULL DEFAULT '', -- 类型: offer, order
+    item_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- offer_id 或 order_id
+    worker VARCHAR(64) NOT NULL DEFAULT '', -- 租用者
+    attempts INTEGER NOT NULL DEFAULT 0, -- 已租用次数, 完成后删除
+    last_error TEXT NOT NULL DEFAULT '', -- 最近一次失败原因
+
+    leased_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 租用时间
+    expires_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 租约到期时间
+    available_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 此时间之后可再次租用(到期或失败后退避)
+);
+CREATE UNIQUE INDEX leases_kind_item_id on leases (kind, item_id);
+CREATE INDEX leases_available_at on leases (available_at);
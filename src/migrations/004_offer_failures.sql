@@ -0,0 +1,17 @@
+ALTER TABLE offers ADD COLUMN fail_count INTEGER NOT NULL DEFAULT 0; -- 连续采集失败次数, 成功后清零
+ALTER TABLE offers ADD COLUMN fail_reason TEXT NOT NULL DEFAULT ''; -- 最近一次采集失败原因
+ALTER TABLE offers ADD COLUMN unavailable_at TIMESTAMP; -- 连续失败达到阈值, 判定为失效的时间
+ALTER TABLE products ADD COLUMN offer_unavailable INTEGER NOT NULL DEFAULT 0; -- 对应offer已失效(1是0否)
+
+CREATE TABLE offer_events(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    offer_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- 1688 offer id
+    kind VARCHAR(16) NOT NULL DEFAULT '', -- unavailable 判定失效, available 恢复
+    detail TEXT NOT NULL DEFAULT '{}', -- 详情, json格式
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 创建时间
+);
+CREATE INDEX offer_events_offer_id on offer_events (offer_id);
+CREATE INDEX offers_unavailable_at on offers (unavailable_at);
+CREATE INDEX products_offer_unavailable on products (offer_unavailable);
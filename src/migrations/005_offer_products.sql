@@ -0,0 +1,16 @@
+CREATE TABLE offer_products(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    offer_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- 1688 offer id
+    product_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae商品ID
+    is_primary INTEGER NOT NULL DEFAULT 0, -- 是否为该商品的主offer(1是0否), 其余为备用
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 创建时间
+);
+CREATE UNIQUE INDEX offer_products_offer_id_product_id on offer_products (offer_id, product_id);
+CREATE INDEX offer_products_product_id on offer_products (product_id);
+
+-- 已有的关联: products.offer_id 作为主offer, offers.product_id 不一致的作为备用
+INSERT OR IGNORE INTO offer_products (offer_id, product_id, is_primary) SELECT offer_id, product_id, 1 FROM products WHERE offer_id > 0;
+INSERT OR IGNORE INTO offer_products (offer_id, product_id, is_primary) SELECT offer_id, product_id, 0 FROM offers WHERE product_id > 0;
+UPDATE offers SET product_id = coalesce((SELECT product_id FROM offer_products l WHERE l.offer_id = offers.offer_id ORDER BY is_primary DESC, id ASC LIMIT 1), 0);
@@ -0,0 +1,12 @@
+CREATE TABLE offer_switches(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    product_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae商品ID
+    from_offer_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- 原主offer, 0为没有
+    to_offer_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- 新主offer
+    reason VARCHAR(16) NOT NULL DEFAULT '', -- deleted, unavailable, price_raised, sku_lost, reselect
+    detail TEXT NOT NULL DEFAULT '{}', -- 详情, json格式
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 创建时间
+);
+CREATE INDEX offer_switches_product_id on offer_switches (product_id);
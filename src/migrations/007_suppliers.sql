@@ -0,0 +1,22 @@
+ALTER TABLE offers ADD COLUMN price_changes INTEGER NOT NULL DEFAULT 0; -- 折扣价变更次数
+ALTER TABLE offers ADD COLUMN sku_changes INTEGER NOT NULL DEFAULT 0; -- SKU变更次数
+
+CREATE TABLE suppliers(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    name VARCHAR(128) NOT NULL DEFAULT '', -- 供货商, 同offers.supplier
+    store_url VARCHAR(255) NOT NULL DEFAULT '', -- 店铺地址
+    offer_count INTEGER NOT NULL DEFAULT 0, -- offer数量
+    delisted_count INTEGER NOT NULL DEFAULT 0, -- 已删除或已失效的offer数量
+    sale30 INTEGER NOT NULL DEFAULT 0, -- 所有offer的月销量合计
+    price_change_rate REAL NOT NULL DEFAULT 0, -- 平均每个offer每30天折扣价变更次数
+    sku_change_rate REAL NOT NULL DEFAULT 0, -- 平均每个offer每30天SKU变更次数
+    score INTEGER NOT NULL DEFAULT 100, -- 可靠性评分0-100
+    blacklisted INTEGER NOT NULL DEFAULT 0, -- 是否拉黑(1是0否), 拉黑的不作为主offer
+    tips TEXT NOT NULL DEFAULT '', -- 备注
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 创建时间
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 统计时间
+);
+CREATE UNIQUE INDEX suppliers_name on suppliers (name);
+CREATE INDEX offers_supplier on offers (supplier);
@@ -0,0 +1,16 @@
+CREATE TABLE sku_mappings(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    product_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae商品ID
+    ae_color VARCHAR(64) NOT NULL DEFAULT '', -- ae颜色, 大写, 同products.sale_info
+    ae_size VARCHAR(32) NOT NULL DEFAULT '', -- ae尺码, 大写, 同products.sale_info
+    offer_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- 1688 offer id
+    offer_color VARCHAR(128) NOT NULL DEFAULT '', -- 1688颜色, skuProps[0]
+    offer_size VARCHAR(64) NOT NULL DEFAULT '', -- 1688尺码, skuProps[1]
+    sku_id VARCHAR(255) NOT NULL DEFAULT '', -- 1688 sku, 同offers.sale_info中detail的key
+    manual INTEGER NOT NULL DEFAULT 0, -- 是否手动设置(1是0否), 手动设置的不会被自动推荐覆盖
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 创建时间
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 更新时间
+);
+CREATE UNIQUE INDEX sku_mappings_product_offer_sku on sku_mappings (product_id, offer_id, ae_color, ae_size);
@@ -0,0 +1 @@
+ALTER TABLE products ADD COLUMN stock_location VARCHAR(64) NOT NULL DEFAULT ''; -- 库存存放位置, 用于拣货单
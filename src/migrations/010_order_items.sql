@@ -0,0 +1,21 @@
+CREATE TABLE order_items(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    order_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- 订单ID
+    product_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae商品ID
+    sku TEXT NOT NULL DEFAULT '', -- ae的sku, "颜色 + 尺码"
+    qty INTEGER NOT NULL DEFAULT 0, -- 数量
+    line_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- 订单行ID
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 下单时间, 同orders.created_at
+);
+CREATE INDEX order_items_order_id on order_items (order_id);
+CREATE INDEX order_items_product_id on order_items (product_id);
+CREATE INDEX order_items_created_at on order_items (created_at);
+
+-- 已有订单: 从 orders.products {product_id: [[sku, qty, line_id]]} 拆分
+INSERT INTO order_items (order_id, product_id, sku, qty, line_id, created_at)
+SELECT o.order_id, CAST(p.key AS INTEGER), coalesce(json_extract(l.value, '$[0]'), ''), coalesce(json_extract(l.value, '$[1]'), 0), coalesce(json_extract(l.value, '$[2]'), 0), o.created_at
+FROM orders o, json_each(o.products) p, json_each(p.value) l
+WHERE json_valid(o.products) AND json_type(o.products) = 'object' AND p.type = 'array' AND l.type = 'array'
+ORDER BY o.id, l.id;
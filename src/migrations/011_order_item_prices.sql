@@ -0,0 +1 @@
+ALTER TABLE order_items ADD COLUMN price INTEGER NOT NULL DEFAULT 0; -- 单价, 同products.price, 订单数据中没有时为0
@@ -0,0 +1,21 @@
+ALTER TABLE orders ADD COLUMN destination VARCHAR(64) NOT NULL DEFAULT ''; -- 目的地(国家)
+ALTER TABLE orders ADD COLUMN shipped_at TIMESTAMP; -- 最早的物流事件时间
+ALTER TABLE orders ADD COLUMN last_event_at TIMESTAMP; -- 最近的物流事件时间
+ALTER TABLE orders ADD COLUMN delivered_at TIMESTAMP; -- 签收时间
+ALTER TABLE orders ADD COLUMN transit_days REAL NOT NULL DEFAULT 0; -- 运输天数, 签收时间-最早的物流事件时间
+
+CREATE TABLE tracking_events(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    order_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae order id
+    lg_order_id CHARACTER(20) NOT NULL DEFAULT '', -- 物流单号
+    status VARCHAR(128) NOT NULL DEFAULT '', -- 物流状态
+    location VARCHAR(128) NOT NULL DEFAULT '', -- 所在地
+    delivered INTEGER NOT NULL DEFAULT 0, -- 是否为签收事件(1是0否)
+    event_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 事件时间
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 记录时间
+);
+CREATE UNIQUE INDEX tracking_events_order_id_event_time_status on tracking_events (order_id, event_time, status);
+CREATE INDEX orders_last_event_at on orders (last_event_at);
+CREATE INDEX orders_delivered_at on orders (delivered_at);
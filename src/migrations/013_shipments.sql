@@ -0,0 +1,18 @@
+CREATE TABLE shipments(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    order_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae order id
+    lg_order_id CHARACTER(20) NOT NULL DEFAULT '', -- 物流单号
+    weight INTEGER NOT NULL DEFAULT 0, -- 包裹重量(克), 0为未统计
+    items TEXT NOT NULL DEFAULT '[]', -- 包裹内的订单行 [[line_id, qty]], 空为整单
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- 创建时间
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 更新时间
+);
+CREATE UNIQUE INDEX shipments_order_id_lg_order_id on shipments (order_id, lg_order_id);
+CREATE INDEX shipments_lg_order_id on shipments (lg_order_id);
+CREATE INDEX shipments_updated_at on shipments (updated_at);
+
+-- 已有订单: 每个订单一个包裹
+INSERT INTO shipments (order_id, lg_order_id, weight, created_at, updated_at)
+SELECT order_id, lg_order_id, weight, created_at, updated_at FROM orders WHERE lg_order_id IS NOT NULL AND lg_order_id != '' ORDER BY id;
@@ -0,0 +1,18 @@
+ALTER TABLE orders ADD COLUMN attention INTEGER NOT NULL DEFAULT 0; -- 需要关注(1是0否)
+CREATE INDEX orders_attention on orders (attention);
+
+CREATE TABLE order_notes(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    order_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae order id
+    source VARCHAR(16) NOT NULL DEFAULT '', -- 来源, ae: 订单备注, admin: 后台添加
+    author VARCHAR(64) NOT NULL DEFAULT '', -- 添加人, ae备注为空
+    content TEXT NOT NULL DEFAULT '', -- 内容
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 添加时间
+);
+CREATE INDEX order_notes_order_id on order_notes (order_id);
+
+-- 已有订单: 当前的备注作为ae备注
+INSERT INTO order_notes (order_id, source, content, created_at)
+SELECT order_id, 'ae', remark, updated_at FROM orders WHERE remark != '' ORDER BY id;
@@ -0,0 +1,14 @@
+CREATE TABLE archived_sales(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+
+    date CHARACTER(10) NOT NULL DEFAULT '', -- 下单日期 YYYY-MM-DD
+    product_id UNSIGNED BIG INT NOT NULL DEFAULT 0, -- ae商品ID
+    sku TEXT NOT NULL DEFAULT '', -- ae的sku, 同order_items.sku
+    qty INTEGER NOT NULL DEFAULT 0, -- 数量
+    revenue INTEGER NOT NULL DEFAULT 0, -- 有单价的商品行的销售额
+    priced_qty INTEGER NOT NULL DEFAULT 0, -- 有单价的数量
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- 最近归档时间
+);
+CREATE UNIQUE INDEX archived_sales_date_product_id_sku on archived_sales (date, product_id, sku);
+CREATE INDEX archived_sales_product_id on archived_sales (product_id);
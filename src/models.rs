@@ -1,5 +1,6 @@
 #![allow(dead_code, unused_imports, unused)]
 
+use crate::config::Settings;
 use serde::{Deserialize, Serialize};
 use serde_json::{from_str, json, Value};
 use sqlx::FromRow;
@@ -48,6 +49,12 @@ pub struct Offer {
     pub updated_at: OffsetDateTime,
     #[serde(with = "show_option_time")]
     pub deleted_at: Option<OffsetDateTime>,
+    pub fail_count: i64,
+    pub fail_reason: String,
+    #[serde(with = "show_option_time")]
+    pub unavailable_at: Option<OffsetDateTime>,
+    pub price_changes: i64,
+    pub sku_changes: i64,
 
     //NewOffer
     pub offer_id: i64,
@@ -113,6 +120,11 @@ impl Offer {
             created_at: OffsetDateTime::now_local().unwrap(),
             updated_at: OffsetDateTime::now_local().unwrap(),
             deleted_at: None,
+            fail_count: 0,
+            fail_reason: String::new(),
+            unavailable_at: None,
+            price_changes: 0,
+            sku_changes: 0,
 
             //NewOffer
             offer_id: no.offer_id,
@@ -133,7 +145,7 @@ impl Offer {
         }
     }
 
-    pub fn update(mut self, no: &NewOffer, cfg: Value) -> Self {
+    pub fn update(mut self, no: &NewOffer, cfg: &Settings) -> Self {
         self.updated_at = OffsetDateTime::now_local().unwrap();
         let today = self.updated_at.date().to_string();
 
@@ -282,15 +294,13 @@ impl Offer {
             }
             None => 0,
         };
-        if self.updated_at - self.created_at
-            > Duration::days(cfg["CHECK_OFFER_SALES_AFTER_DAYS"].as_i64().unwrap_or(90))
+        //销量小于sku数
+        if self.updated_at - self.created_at > Duration::days(cfg.check_offer_sales_after_days)
+            && sale60 < (sale_info["detail"].as_object().unwrap().len() as i64)
         {
-            if sale60 < (sale_info["detail"].as_object().unwrap().len() as i64) {
-                //销量小于sku数
-                self.tips += "销量低下架否?;";
-                if self.pending == 0 {
-                    self.pending = -1;
-                }
+            self.tips += "销量低下架否?;";
+            if self.pending == 0 {
+                self.pending = -1;
             }
         }
 
@@ -304,6 +314,7 @@ impl Offer {
         //price不更新
         if self.better_price != no.better_price {
             self.better_price = no.better_price;
+            self.price_changes += 1;
             self.tips += &self.discount.to_string();
             self.discount = (self.price - self.better_price) * 100 / self.price;
             self.tips += &(" => ".to_string() + &self.discount.to_string() + " 折扣价变更;");
@@ -319,6 +330,9 @@ impl Offer {
         }
         //sku_info_use保持原样
         if &self.sku_info_use != &no.sku_info {
+            if self.sku_info != no.sku_info {
+                self.sku_changes += 1;
+            }
             self.sku_info = no.sku_info.clone();
             self.tips += "SKU变更;";
             if self.pending == 0 {
@@ -375,6 +389,8 @@ pub struct Product {
     pub updated_at: OffsetDateTime,
     #[serde(with = "show_option_time")]
     pub deleted_at: Option<OffsetDateTime>,
+    pub offer_unavailable: i64,
+    pub stock_location: String,
 
     //NewProduct
     pub product_id: i64,
@@ -406,6 +422,8 @@ impl Product {
             created_at: OffsetDateTime::now_local().unwrap(),
             updated_at: OffsetDateTime::now_local().unwrap(),
             deleted_at: None,
+            offer_unavailable: 0,
+            stock_location: String::new(),
 
             //NewProduct
             product_id: np.product_id,
@@ -451,6 +469,15 @@ pub struct Order {
     pub created_at: OffsetDateTime,
     #[serde(with = "show_time")]
     pub updated_at: OffsetDateTime,
+    pub destination: String,
+    #[serde(with = "show_option_time")]
+    pub shipped_at: Option<OffsetDateTime>,
+    #[serde(with = "show_option_time")]
+    pub last_event_at: Option<OffsetDateTime>,
+    #[serde(with = "show_option_time")]
+    pub delivered_at: Option<OffsetDateTime>,
+    pub transit_days: f64,
+    pub attention: i64,
 
     //NewOrder
     pub order_id: i64,
@@ -469,6 +496,12 @@ impl Order {
             used_stock: String::new(),
             created_at: OffsetDateTime::now_local().unwrap(),
             updated_at: OffsetDateTime::now_local().unwrap(),
+            destination: String::new(),
+            shipped_at: None,
+            last_event_at: None,
+            delivered_at: None,
+            transit_days: 0.0,
+            attention: 0,
 
             //NewOrder
             order_id: no.order_id,
@@ -0,0 +1,199 @@
+use crate::config::Settings;
+use anyhow::Result;
+use serde_json::{from_str, json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+//默认的尺码别名, 配置中的SIZE_ALIASES在此基础上增加或覆盖
+const SIZE_ALIASES: [(&str, &str); 7] = [
+    ("XXL", "2XL"),
+    ("XXXL", "3XL"),
+    ("XXXXL", "4XL"),
+    ("XXXXXL", "5XL"),
+    ("XXXXXXL", "6XL"),
+    ("均码", "ONE SIZE"),
+    ("FREE SIZE", "ONE SIZE"),
+];
+
+//sku颜色和尺码名称规范化: 去空白, 转大写, 再按别名表转为统一名称
+//products/orders中以ae的sku为键的数据, 以及offers中以颜色/尺码为键的数据, 读写时都经过这里
+#[derive(Clone, Default)]
+pub struct Normalizer {
+    sizes: HashMap<String, String>,
+    colors: HashMap<String, String>,
+}
+
+fn key(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_uppercase()
+}
+
+fn aliases(conf: &BTreeMap<String, String>, defaults: &[(&str, &str)]) -> HashMap<String, String> {
+    let mut aliases: HashMap<String, String> = defaults
+        .iter()
+        .map(|(from, to)| (key(from), key(to)))
+        .collect();
+    for (from, to) in conf {
+        aliases.insert(key(from), key(to));
+    }
+    aliases
+}
+
+impl Normalizer {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            sizes: aliases(&settings.size_aliases, &SIZE_ALIASES),
+            colors: aliases(&settings.color_aliases, &[]),
+        }
+    }
+
+    pub fn size(&self, size: &str) -> String {
+        let size = key(size);
+        self.sizes.get(&size).cloned().unwrap_or(size)
+    }
+
+    pub fn color(&self, color: &str) -> String {
+        let color = key(color);
+        self.colors.get(&color).cloned().unwrap_or(color)
+    }
+
+    //ae的sku文本 "颜色 + 尺码"
+    pub fn sku(&self, text: &str) -> (String, String) {
+        let mut parts = text.split(" + ");
+        let color = self.color(parts.next().unwrap_or(""));
+        let size = self.size(parts.next().unwrap_or(""));
+        (color, size)
+    }
+
+    //{颜色:{尺码:数量}}, 规范化后名称相同的数量合并
+    pub fn sku_map(&self, info: &Value) -> Value {
+        let mut res = Map::new();
+        if let Some(colors) = info.as_object() {
+            for (color, sizes) in colors {
+                let color = res.entry(self.color(color)).or_insert_with(|| json!({}));
+                if let Some(sizes) = sizes.as_object() {
+                    for (size, n) in sizes {
+                        let size = self.size(size);
+                        color[&size] = match (color[&size].as_i64(), n.as_i64()) {
+                            (Some(a), Some(b)) => json!(a + b),
+                            _ => n.clone(),
+                        };
+                    }
+                }
+            }
+        }
+        Value::Object(res)
+    }
+
+    //products.sale_info/stock_info, 空字符串表示还没有数据, 原样返回
+    pub fn sku_map_str(&self, info: &str) -> Result<String> {
+        if info.trim().is_empty() {
+            return Ok(info.to_string());
+        }
+        Ok(self.sku_map(&from_str(info)?).to_string())
+    }
+
+    //offer的sale_info: color和size按名称规范化, detail是1688的sku, 保持原样
+    pub fn offer_sale_info(&self, sale_info: &str) -> Result<String> {
+        let mut info: Value = from_str(sale_info)?;
+        for (field, by_size) in [("color", false), ("size", true)] {
+            if let Some(names) = info[field].as_object() {
+                let mut res = Map::new();
+                for (name, n) in names {
+                    let name = if by_size {
+                        self.size(name)
+                    } else {
+                        self.color(name)
+                    };
+                    let merged = match (res.get(&name).and_then(|v| v.as_i64()), n.as_i64()) {
+                        (Some(a), Some(b)) => json!(a + b),
+                        _ => n.clone(),
+                    };
+                    res.insert(name, merged);
+                }
+                info[field] = Value::Object(res);
+            }
+        }
+        Ok(info.to_string())
+    }
+
+    //sale_record最后一项是上次采集的可订数量, 规范化后才能和新数据按名称比较
+    pub fn offer_sale_record(&self, sale_record: &str) -> Result<String> {
+        let mut records: Vec<Value> = from_str(sale_record)?;
+        if let Some(last) = records.last_mut() {
+            *last = from_str(&self.offer_sale_info(&last.to_string())?)?;
+        }
+        Ok(json!(records).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer() -> Normalizer {
+        let mut settings = Settings::default();
+        settings
+            .size_aliases
+            .insert("extra large".to_string(), "xl".to_string());
+        settings
+            .size_aliases
+            .insert("free size".to_string(), "F".to_string());
+        settings
+            .color_aliases
+            .insert("黑".to_string(), "黑色".to_string());
+        Normalizer::from_settings(&settings)
+    }
+
+    #[test]
+    fn names() {
+        let n = normalizer();
+        assert_eq!(n.size("  xxl "), "2XL");
+        assert_eq!(n.size("Extra   Large"), "XL");
+        assert_eq!(n.size("均码"), "ONE SIZE");
+        //配置覆盖默认别名
+        assert_eq!(n.size("Free Size"), "F");
+        assert_eq!(n.size("m"), "M");
+        assert_eq!(n.color("黑"), "黑色");
+        assert_eq!(n.color(" light  blue"), "LIGHT BLUE");
+    }
+
+    #[test]
+    fn sku_text() {
+        let n = normalizer();
+        assert_eq!(n.sku("黑 + xxl"), ("黑色".to_string(), "2XL".to_string()));
+        assert_eq!(n.sku("red"), ("RED".to_string(), "".to_string()));
+    }
+
+    #[test]
+    fn sku_map_merges_same_names() {
+        let n = normalizer();
+        let info = json!({"黑": {"xxl": 1, "2XL": 2}, "黑色": {"m": 3}});
+        assert_eq!(n.sku_map(&info), json!({"黑色": {"2XL": 3, "M": 3}}));
+        assert_eq!(n.sku_map_str(" ").unwrap(), " ");
+        assert!(n.sku_map_str("{").is_err());
+    }
+
+    #[test]
+    fn offer_sale_info_keeps_detail() {
+        let n = normalizer();
+        let info = r#"{"color":{"黑":1,"黑色":2},"size":{"xxl":3},"detail":{"黑&gt;xxl":3}}"#;
+        let res: Value = from_str(&n.offer_sale_info(info).unwrap()).unwrap();
+        assert_eq!(
+            res,
+            json!({"color": {"黑色": 3}, "size": {"2XL": 3}, "detail": {"黑&gt;xxl": 3}})
+        );
+    }
+
+    #[test]
+    fn offer_sale_record_normalizes_last() {
+        let n = normalizer();
+        let record = r#"[{"date":"2026-10-01","count":1},{"color":{"黑":1},"size":{"xxl":3}}]"#;
+        let res: Value = from_str(&n.offer_sale_record(record).unwrap()).unwrap();
+        assert_eq!(
+            res,
+            json!([{"date": "2026-10-01", "count": 1}, {"color": {"黑色": 1}, "size": {"2XL": 3}}])
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use crate::models::Order;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{from_str, Value};
+use sqlx::{query, FromRow, QueryBuilder, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+
+//订单中的商品行, 由orders.products拆分, 按商品查询和统计时使用
+#[derive(Serialize, FromRow, Clone)]
+pub struct OrderItem {
+    pub id: i64,
+    pub order_id: i64,
+    pub product_id: i64,
+    pub sku: String,
+    pub qty: i64,
+    pub line_id: i64,
+    pub price: i64,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+}
+
+//(product_id, sku, qty, line_id, price)
+pub type ItemLine = (i64, String, i64, i64, i64);
+
+//orders.products {product_id: [[sku, qty, line_id, price?]]}, 单价可选
+pub fn parse(products: &str) -> Result<Vec<ItemLine>> {
+    let pds: HashMap<i64, Vec<Vec<Value>>> = from_str(products)?;
+    let mut items = vec![];
+    for (pid, lines) in pds {
+        for line in lines {
+            let sku = match line.first().and_then(|s| s.as_str()) {
+                Some(sku) => sku.to_string(),
+                None => {
+                    return Err(anyhow!("订单商品格式错误: {:?}", line));
+                }
+            };
+            let num = |i: usize| line.get(i).and_then(|n| n.as_i64()).unwrap_or(0);
+            items.push((pid, sku, num(1), num(2), num(3)));
+        }
+    }
+    items.sort_by_key(|i| (i.0, i.3));
+    Ok(items)
+}
+
+//新订单写入商品行
+pub async fn insert(conn: &mut SqliteConnection, order: &Order) -> Result<u64> {
+    let items = parse(&order.products)?;
+    if items.is_empty() {
+        return Ok(0);
+    }
+    let mut query_builder = QueryBuilder::new(
+        "insert into order_items (order_id, product_id, sku, qty, line_id, price, created_at) ",
+    );
+    query_builder.push_values(&items, |mut b, item| {
+        b.push_bind(order.order_id)
+            .push_bind(item.0)
+            .push_bind(&item.1)
+            .push_bind(item.2)
+            .push_bind(item.3)
+            .push_bind(item.4)
+            .push_bind(order.created_at);
+    });
+    Ok(query_builder
+        .build()
+        .execute(&mut *conn)
+        .await?
+        .rows_affected())
+}
+
+pub async fn of_orders(conn: &mut SqliteConnection, order_ids: &[i64]) -> Result<Vec<OrderItem>> {
+    if order_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut query_builder = QueryBuilder::new("select * from order_items where order_id in (");
+    let mut separated = query_builder.separated(",");
+    for order_id in order_ids {
+        separated.push_bind(order_id);
+    }
+    query_builder.push(") order by order_id, product_id, line_id");
+    Ok(query_builder.build_query_as().fetch_all(&mut *conn).await?)
+}
+
+//删除已不存在的订单的商品行
+pub async fn purge(db: &SqlitePool) -> Result<u64> {
+    Ok(
+        query("delete from order_items where order_id not in (select order_id from orders)")
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_sorted_by_product_and_line() {
+        let items =
+            parse(r#"{"2":[["黑色 + M",1,5,1200]],"1":[["红色 + L",2,4],["红色 + S",1,3,900]]}"#)
+                .unwrap();
+        assert_eq!(
+            items,
+            vec![
+                (1, "红色 + S".to_string(), 1, 3, 900),
+                (1, "红色 + L".to_string(), 2, 4, 0),
+                (2, "黑色 + M".to_string(), 1, 5, 1200),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_and_invalid() {
+        assert!(parse("{}").unwrap().is_empty());
+        assert!(parse(r#"{"1":[[]]}"#).is_err());
+        assert!(parse(r#"{"1":[[1,2]]}"#).is_err());
+        assert!(parse(r#"{"x":[["红色",1]]}"#).is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{query, query_as, FromRow, SqliteConnection, SqlitePool};
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+
+//备注来源
+pub const AE: &str = "ae";
+pub const ADMIN: &str = "admin";
+
+//订单备注记录, 按时间追加, 不修改
+#[derive(Serialize, FromRow)]
+pub struct OrderNote {
+    pub id: i64,
+    pub order_id: i64,
+    pub source: String,
+    pub author: String,
+    pub content: String,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+}
+
+pub async fn add(
+    conn: &mut SqliteConnection,
+    order_id: i64,
+    source: &str,
+    author: &str,
+    content: &str,
+) -> Result<i64> {
+    Ok(query(
+        "insert into order_notes (order_id,source,author,content,created_at) values (?,?,?,?,?)",
+    )
+    .bind(order_id)
+    .bind(source)
+    .bind(author)
+    .bind(content)
+    .bind(OffsetDateTime::now_local()?)
+    .execute(&mut *conn)
+    .await?
+    .last_insert_rowid())
+}
+
+//采集到的ae订单备注, 和上一条ae备注不同时才记录
+pub async fn add_remark(conn: &mut SqliteConnection, order_id: i64, remark: &str) -> Result<bool> {
+    if remark.is_empty() {
+        return Ok(false);
+    }
+    let last: Option<(String,)> = query_as(
+        "select content from order_notes where order_id=? and source=? order by id desc limit 1",
+    )
+    .bind(order_id)
+    .bind(AE)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if last.is_some_and(|(content,)| content == remark) {
+        return Ok(false);
+    }
+    add(conn, order_id, AE, "", remark).await?;
+    Ok(true)
+}
+
+pub async fn of_order(db: &SqlitePool, order_id: i64) -> Result<Vec<OrderNote>> {
+    Ok(
+        query_as("select * from order_notes where order_id=? order by created_at asc, id asc")
+            .bind(order_id)
+            .fetch_all(db)
+            .await?,
+    )
+}
+
+//删除已不存在的订单的备注
+pub async fn purge(db: &SqlitePool) -> Result<u64> {
+    Ok(
+        query("delete from order_notes where order_id not in (select order_id from orders)")
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
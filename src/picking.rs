@@ -0,0 +1,291 @@
+use crate::analytics::DateRange;
+use crate::jobs::{self, JobCtx};
+use crate::models::Order;
+use crate::normalize::Normalizer;
+use crate::order_items::{self, OrderItem};
+use crate::skus::{self, SkuMap};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{from_str, json, Value};
+use sqlx::{query_as, QueryBuilder, SqlitePool};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+//拣货单的一行: 同一货号/颜色/尺码的数量合计
+#[derive(Serialize, Default)]
+pub struct PickLine {
+    pub location: String,
+    pub model_id: String,
+    pub product_id: i64,
+    pub color: String,
+    pub size: String,
+    pub offer_id: i64,
+    pub offer_color: String,
+    pub offer_size: String,
+    pub quantity: i64,
+    pub from_stock: i64, //用库存发货
+    pub to_buy: i64,     //需向供货商采购
+    pub order_ids: Vec<i64>,
+}
+
+#[derive(Default)]
+struct PickProduct {
+    location: String,
+    model_id: String,
+    offer_id: i64,
+    maps: SkuMap,
+}
+
+//orders.used_stock {"product_id-color-size":quantity}
+fn used_stock(norm: &Normalizer, used_stock: &str) -> HashMap<(i64, String, String), i64> {
+    let used: HashMap<String, i64> = from_str(used_stock).unwrap_or_default();
+    let mut res = HashMap::new();
+    for (k, n) in used {
+        if let Some((pid, sku)) = k.split_once('-') {
+            if let (Ok(pid), Some((color, size))) = (pid.parse::<i64>(), sku.rsplit_once('-')) {
+                *res.entry((pid, norm.color(color), norm.size(size)))
+                    .or_insert(0) += n;
+            }
+        }
+    }
+    res
+}
+
+async fn product(db: &SqlitePool, norm: &Normalizer, product_id: i64) -> Result<PickProduct> {
+    let row: Option<(String, i64, String)> = query_as("select p.stock_location, p.offer_id, coalesce(o.model_id, p.model_id) from products p left join offers o on o.offer_id=p.offer_id where p.product_id=?")
+        .bind(product_id)
+        .fetch_optional(db)
+        .await?;
+    match row {
+        Some((location, offer_id, model_id)) => Ok(PickProduct {
+            location,
+            model_id,
+            offer_id,
+            maps: skus::by_sku(skus::mappings(db, norm, product_id, offer_id).await?),
+        }),
+        None => Ok(PickProduct::default()),
+    }
+}
+
+//指定订单, 或按下单日期范围, 两者都没有时为今天的订单
+pub async fn build(
+    db: &SqlitePool,
+    norm: &Normalizer,
+    order_ids: &[i64],
+    range: Option<DateRange>,
+) -> Result<(usize, Vec<PickLine>)> {
+    let mut query_builder = QueryBuilder::new("select * from orders where ");
+    if !order_ids.is_empty() {
+        query_builder.push("order_id in (");
+        let mut separated = query_builder.separated(",");
+        for order_id in order_ids {
+            separated.push_bind(order_id);
+        }
+        query_builder.push(")");
+    } else {
+        let today = OffsetDateTime::now_local()?.date();
+        let (from, to) = range.unwrap_or((today, today));
+        query_builder.push("created_at >= ");
+        query_builder.push_bind(from);
+        query_builder.push(" and created_at < ");
+        query_builder.push_bind(to + Duration::days(1));
+    }
+    query_builder.push(" order by created_at asc");
+    let orders: Vec<Order> = query_builder.build_query_as().fetch_all(db).await?;
+
+    let order_ids: Vec<i64> = orders.iter().map(|o| o.order_id).collect();
+    let mut items: HashMap<i64, Vec<OrderItem>> = HashMap::new();
+    for item in order_items::of_orders(&mut *db.acquire().await?, &order_ids).await? {
+        items.entry(item.order_id).or_default().push(item);
+    }
+
+    let mut products: HashMap<i64, PickProduct> = HashMap::new();
+    let mut lines: BTreeMap<(i64, String, String), PickLine> = BTreeMap::new();
+    for order in orders.iter() {
+        let mut stock = used_stock(norm, &order.used_stock);
+        for item in items.remove(&order.order_id).unwrap_or_default() {
+            let pid = item.product_id;
+            let quantity = item.qty;
+            if let Entry::Vacant(e) = products.entry(pid) {
+                e.insert(product(db, norm, pid).await?);
+            }
+            let pd = &products[&pid];
+            let (color, size) = norm.sku(&item.sku);
+            //订单使用的库存先抵扣
+            let from_stock = match stock.get_mut(&(pid, color.clone(), size.clone())) {
+                Some(left) => {
+                    let used = quantity.min(*left);
+                    *left -= used;
+                    used
+                }
+                None => 0,
+            };
+            let line = lines
+                .entry((pid, color.clone(), size.clone()))
+                .or_insert_with(|| {
+                    let map = pd.maps.get(&(color.clone(), size.clone()));
+                    PickLine {
+                        location: pd.location.clone(),
+                        model_id: pd.model_id.clone(),
+                        product_id: pid,
+                        offer_id: pd.offer_id,
+                        offer_color: map.map(|m| m.offer_color.clone()).unwrap_or_default(),
+                        offer_size: map.map(|m| m.offer_size.clone()).unwrap_or_default(),
+                        color,
+                        size,
+                        ..Default::default()
+                    }
+                });
+            line.quantity += quantity;
+            line.from_stock += from_stock;
+            line.to_buy += quantity - from_stock;
+            if !line.order_ids.contains(&order.order_id) {
+                line.order_ids.push(order.order_id);
+            }
+        }
+    }
+
+    //按库位和货号排序, 方便依次拣货
+    let mut lines: Vec<PickLine> = lines.into_values().collect();
+    lines.sort_by(|a, b| {
+        (&a.location, &a.model_id, a.product_id, &a.color, &a.size).cmp(&(
+            &b.location,
+            &b.model_id,
+            b.product_id,
+            &b.color,
+            &b.size,
+        ))
+    });
+    Ok((orders.len(), lines))
+}
+
+const TITLES: [&str; 11] = [
+    "库位",
+    "货号",
+    "商品ID",
+    "颜色",
+    "尺码",
+    "1688颜色",
+    "1688尺码",
+    "数量",
+    "用库存",
+    "需采购",
+    "订单",
+];
+
+fn order_ids(line: &PickLine) -> String {
+    line.order_ids
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn xlsx(lines: &[PickLine], file_path: &Path) -> Result<()> {
+    use rust_xlsxwriter::{Format, Workbook};
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    worksheet.set_name("picking")?;
+
+    let bold = Format::new().set_bold();
+    for (col, title) in TITLES.iter().enumerate() {
+        worksheet.write_with_format(0, col as u16, *title, &bold)?;
+    }
+    worksheet.set_column_width(2, 20)?;
+    worksheet.set_column_width(10, 40)?;
+    for (i, line) in lines.iter().enumerate() {
+        let row = i as u32 + 1;
+        worksheet.write(row, 0, &line.location)?;
+        worksheet.write(row, 1, &line.model_id)?;
+        worksheet.write(row, 2, line.product_id.to_string())?;
+        worksheet.write(row, 3, &line.color)?;
+        worksheet.write(row, 4, &line.size)?;
+        worksheet.write(row, 5, &line.offer_color)?;
+        worksheet.write(row, 6, &line.offer_size)?;
+        worksheet.write(row, 7, line.quantity as f64)?;
+        worksheet.write(row, 8, line.from_stock as f64)?;
+        worksheet.write(row, 9, line.to_buy as f64)?;
+        worksheet.write(row, 10, order_ids(line))?;
+    }
+    workbook.save(file_path)?;
+    Ok(())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html(lines: &[PickLine], order_count: usize) -> Result<String> {
+    let now = OffsetDateTime::now_local()?.format(&Rfc3339)?;
+    let (quantity, from_stock, to_buy) = lines.iter().fold((0, 0, 0), |s, l| {
+        (s.0 + l.quantity, s.1 + l.from_stock, s.2 + l.to_buy)
+    });
+    let mut html = String::from("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>拣货单</title><style>body{font-family:sans-serif;font-size:12px}table{border-collapse:collapse;width:100%}th,td{border:1px solid #333;padding:3px 5px}th{background:#eee}td.n{text-align:right}tr.buy td{font-weight:bold}@media print{th{background:none}}</style></head><body>");
+    write!(
+        html,
+        "<h3>拣货单</h3><p>{} 订单数: {} 件数: {} 用库存: {} 需采购: {}</p><table><tr>",
+        now, order_count, quantity, from_stock, to_buy
+    )?;
+    for title in TITLES {
+        write!(html, "<th>{}</th>", title)?;
+    }
+    html.push_str("</tr>");
+    for line in lines {
+        //需要采购的行加粗
+        let class = if line.to_buy > 0 {
+            " class=\"buy\""
+        } else {
+            ""
+        };
+        write!(
+            html,
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td></tr>",
+            class,
+            escape(&line.location),
+            escape(&line.model_id),
+            line.product_id,
+            escape(&line.color),
+            escape(&line.size),
+            escape(&line.offer_color),
+            escape(&line.offer_size),
+            line.quantity,
+            line.from_stock,
+            line.to_buy,
+            order_ids(line),
+        )?;
+    }
+    html.push_str("</table></body></html>");
+    Ok(html)
+}
+
+//生成拣货单文件, format为xlsx或html
+pub async fn export(
+    db: &SqlitePool,
+    norm: &Normalizer,
+    tmp_dir: &str,
+    order_ids: &[i64],
+    range: Option<DateRange>,
+    format: &str,
+    job: &JobCtx,
+) -> Result<Value> {
+    let (order_count, lines) = build(db, norm, order_ids, range).await?;
+    job.progress(order_count as i64, order_count as i64).await?;
+
+    //文件名带上任务id, 避免同时导出时互相覆盖
+    let file_name = format!("picking-{}.{}", job.id, format);
+    let file_path = PathBuf::from(tmp_dir).join(&file_name);
+    if format == "html" {
+        std::fs::write(&file_path, html(&lines, order_count)?)?;
+    } else {
+        xlsx(&lines, &file_path)?;
+    }
+    let mut res = jobs::file_result(&file_name);
+    res["orders"] = json!(order_count);
+    res["lines"] = json!(lines.len());
+    Ok(res)
+}
@@ -0,0 +1,52 @@
+use crate::analytics;
+use crate::archive::{self, Search};
+use crate::types::{ok, AEState, AeError, Res};
+use axum::extract::{Json, State};
+use serde::Deserialize;
+use serde_json::json;
+use std::cmp::max;
+
+#[derive(Deserialize)]
+pub struct SearchReq {
+    //offers, products, orders, order_items, shipments, tracking_events, order_notes
+    table: String,
+    //按该表的offer_id/product_id/order_id筛选
+    id: Option<i64>,
+    //orders按包含的商品筛选
+    product_id: Option<i64>,
+    //创建日期 "YYYY-MM-DD"或"YYYY-MM-DD~YYYY-MM-DD"
+    #[serde(default)]
+    date: String,
+    #[serde(default)]
+    page: i64,
+    #[serde(default)]
+    per_page: i64,
+}
+//查询已归档的数据
+pub async fn admin_archive_search(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Json(req): Json<SearchReq>,
+) -> Result<Res, AeError> {
+    let search = Search {
+        table: req.table,
+        id: req.id,
+        product_id: req.product_id,
+        range: if req.date.trim().is_empty() {
+            None
+        } else {
+            Some(analytics::parse_date_range(&req.date)?)
+        },
+        page: max(1, req.page),
+        per_page: if req.per_page <= 0 { 20 } else { req.per_page },
+    };
+    let (total, rows) = archive::search(&db, &settings, &search).await?;
+    return ok(json!({
+        "page": search.page,
+        "per_page": search.per_page,
+        "total": total,
+        "rows": rows,
+    }));
+}
@@ -0,0 +1,66 @@
+use crate::backup;
+use crate::jobs;
+use crate::maintenance;
+use crate::migrations;
+use crate::scheduler;
+use crate::types::{err, ok, AEState, AeError, Res};
+use axum::extract::{Json, State};
+use serde::Deserialize;
+use serde_json::json;
+
+//备份文件列表, 最新的在前
+pub async fn admin_backup_list(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+) -> Result<Res, AeError> {
+    return ok(json!({
+        "schema_version": migrations::schema_version(&db).await?,
+        "files": backup::list(&settings)?,
+    }));
+}
+
+//立即备份, 同 /admin/schedule/run/backup
+pub async fn admin_backup_create(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+) -> Result<Res, AeError> {
+    match scheduler::run_task(&db, &settings, maintenance::BACKUP).await {
+        Ok(id) => {
+            return ok(json!(id));
+        }
+        Err(e) => {
+            return err(e.to_string());
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RestoreReq {
+    //备份文件名, 见 /admin/backup/list
+    file: String,
+}
+//从备份恢复的后台任务, 先检查备份的数据库版本
+pub async fn admin_backup_restore(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Json(req): Json<RestoreReq>,
+) -> Result<Res, AeError> {
+    if !backup::list(&settings)?.iter().any(|f| f.file == req.file) {
+        return err("没有该备份文件".to_string());
+    }
+    let job_db = db.clone();
+    let id = jobs::start(
+        &db,
+        jobs::RESTORE,
+        json!({ "file": req.file }),
+        move |_job| async move { backup::restore(&job_db, &settings, &req.file).await },
+    )
+    .await?;
+    return ok(json!(id));
+}
@@ -0,0 +1,113 @@
+use crate::leases;
+use crate::types::{ok, AEState, AeError, Res};
+use axum::extract::{Query, State};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::query_as;
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+#[derive(Deserialize)]
+pub struct StatusReq {
+    //按最近多少分钟的采集速度估算剩余时间, 默认60
+    minutes: Option<i64>,
+}
+//今天的采集进度
+pub async fn admin_crawl_status(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Query(req): Query<StatusReq>,
+) -> Result<Res, AeError> {
+    let now = OffsetDateTime::now_local()?;
+    let today = now.date();
+    let minutes = req.minutes.unwrap_or(60).clamp(1, 24 * 60);
+    let since = now - Duration::minutes(minutes);
+
+    //offer: 今天已更新的不再采集, 已失效的不计入
+    let (offers_done,): (i64,) = query_as("select count(id) from offers where updated_at >= ? and deleted_at is null and unavailable_at is null")
+        .bind(today)
+        .fetch_one(&db)
+        .await?;
+    let (offers_stale,): (i64,) = query_as("select count(id) from offers where updated_at < ? and deleted_at is null and unavailable_at is null")
+        .bind(today)
+        .fetch_one(&db)
+        .await?;
+    let (offers_recent,): (i64,) =
+        query_as("select count(id) from offers where updated_at >= ? and deleted_at is null")
+            .bind(since)
+            .fetch_one(&db)
+            .await?;
+    let (offers_failing, offers_unavailable): (i64, i64) = query_as("select coalesce(sum(fail_count > 0 and unavailable_at is null),0), coalesce(sum(unavailable_at is not null),0) from offers where deleted_at is null")
+        .fetch_one(&db)
+        .await?;
+
+    //订单: 按包裹统计, 条件同orders::next
+    let (orders_done,): (i64,) =
+        query_as("select count(id) from shipments where updated_at >= ? and weight > 0")
+            .bind(today)
+            .fetch_one(&db)
+            .await?;
+    let (orders_waiting,): (i64,) = query_as("select count(s.id) from shipments s join orders o on o.order_id=s.order_id where s.updated_at < ? and o.created_at between ? and ? and s.weight = 0 and s.lg_order_id != ''")
+        .bind(today)
+        .bind(today - Duration::days(60))
+        .bind(today - Duration::days(3))
+        .fetch_one(&db)
+        .await?;
+    let (orders_recent,): (i64,) =
+        query_as("select count(id) from shipments where updated_at >= ? and weight > 0")
+            .bind(since)
+            .fetch_one(&db)
+            .await?;
+
+    //正在租用及最近失败的
+    let leased_now = leases::now()?;
+    let leased: Vec<(String, i64, i64)> = query_as("select kind, coalesce(sum(expires_at > ?),0), coalesce(sum(last_error != '' and available_at > ?),0) from leases group by kind")
+        .bind(leased_now)
+        .bind(leased_now)
+        .fetch_all(&db)
+        .await?;
+    let lease_of = |kind: &str| {
+        leased
+            .iter()
+            .find(|l| l.0 == kind)
+            .map(|l| (l.1, l.2))
+            .unwrap_or((0, 0))
+    };
+    let (offers_leased, offers_retrying) = lease_of(leases::OFFER);
+    let (orders_leased, orders_retrying) = lease_of(leases::SHIPMENT);
+
+    //按最近的速度估算剩余分钟数, 最近没有采集则为null
+    let eta = |left: i64, recent: i64| {
+        if left == 0 {
+            Some(0)
+        } else if recent > 0 {
+            Some((left * minutes + recent - 1) / recent)
+        } else {
+            None
+        }
+    };
+
+    return ok(json!({
+        "time": now.format(&Rfc3339)?,
+        "minutes": minutes,
+        "offers": {
+            "done": offers_done,
+            "stale": offers_stale,
+            "leased": offers_leased,
+            "retrying": offers_retrying,
+            "failing": offers_failing,
+            "unavailable": offers_unavailable,
+            "recent": offers_recent,
+            "eta_minutes": eta(offers_stale, offers_recent),
+        },
+        "orders": {
+            "done": orders_done,
+            "waiting": orders_waiting,
+            "leased": orders_leased,
+            "retrying": orders_retrying,
+            "recent": orders_recent,
+            "eta_minutes": eta(orders_waiting, orders_recent),
+        },
+    }));
+}
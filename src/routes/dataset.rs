@@ -0,0 +1,81 @@
+use crate::dataset;
+use crate::jobs;
+use crate::types::{err, ok, AEState, AeError, Res};
+use axum::extract::{Multipart, Query, State};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+
+//导出全部数据的后台任务, 完成后通过 /admin/jobs/file/:id 下载ndjson文件
+pub async fn admin_data_export(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+) -> Result<Res, AeError> {
+    let tmp_dir = PathBuf::from(&settings.tmp_dir);
+    let job_db = db.clone();
+    let id = jobs::start(&db, jobs::EXPORT_DATA, json!({}), move |job| async move {
+        //文件名带上任务id, 避免同时导出时互相覆盖
+        let file_name = format!("export-{}.ndjson", job.id);
+        let counts = dataset::export(&job_db, &tmp_dir.join(&file_name), Some(&job)).await?;
+        let mut res = jobs::file_result(&file_name);
+        res["counts"] = counts;
+        Ok(res)
+    })
+    .await?;
+    return ok(json!(id));
+}
+
+#[derive(Deserialize)]
+pub struct ImportOpt {
+    //merge(默认) 或 replace
+    mode: Option<String>,
+}
+//上传export导出的ndjson文件, 在后台任务中导入
+pub async fn admin_data_import(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Query(opt): Query<ImportOpt>,
+    mut multipart: Multipart,
+) -> Result<Res, AeError> {
+    let mode = match opt.mode.as_deref() {
+        None | Some(dataset::MERGE) => dataset::MERGE,
+        Some(dataset::REPLACE) => dataset::REPLACE,
+        _ => {
+            return err("mode只能是merge或replace".to_string());
+        }
+    };
+    let mut data = None;
+    while let Some(field) = multipart.next_field().await? {
+        if field.file_name().is_some() {
+            data = Some(field.bytes().await?);
+            break;
+        }
+    }
+    let data = match data {
+        Some(data) => data,
+        None => {
+            return err("没有上传文件".to_string());
+        }
+    };
+
+    let tmp_dir = PathBuf::from(&settings.tmp_dir);
+    let job_db = db.clone();
+    let id = jobs::start(
+        &db,
+        jobs::IMPORT_DATA,
+        json!({ "mode": mode, "size": data.len() }),
+        move |job| async move {
+            let file_path = tmp_dir.join(format!("import-{}.ndjson", job.id));
+            std::fs::write(&file_path, &data)?;
+            let res = dataset::import(&job_db, &file_path, mode, Some(&job)).await;
+            std::fs::remove_file(&file_path)?;
+            res
+        },
+    )
+    .await?;
+    return ok(json!(id));
+}
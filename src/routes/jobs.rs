@@ -0,0 +1,194 @@
+use crate::jobs::Job;
+use crate::scheduler;
+use crate::types::{err, ok, AEState, AeError, Res};
+use axum::{
+    body::Body,
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::{from_str, json, Value};
+use sqlx::{query_as, QueryBuilder};
+use std::{
+    cmp::{max, min},
+    fs,
+    path::PathBuf,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+pub async fn admin_job_get(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(id): Path<i64>,
+) -> Result<Res, AeError> {
+    let job_: Option<Job> = query_as("select * from jobs where id = ?1")
+        .bind(id)
+        .fetch_optional(&db)
+        .await?;
+    if let Some(job) = job_ {
+        return ok(json!(job));
+    } else {
+        return err("not found".to_string());
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SJReq {
+    page: i64,
+    per_page: i64,
+    kind: String,
+    status: String,
+}
+pub async fn admin_job_show(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Json(mut search): Json<SJReq>,
+) -> Result<Res, AeError> {
+    let mut total_query_builder = QueryBuilder::new("select count(id) from jobs where 1=1 ");
+    let mut jobs_query_builder = QueryBuilder::new("select * from jobs where 1=1 ");
+
+    if !search.kind.trim().is_empty() {
+        total_query_builder.push(" and kind = ");
+        total_query_builder.push_bind(&search.kind);
+        jobs_query_builder.push(" and kind = ");
+        jobs_query_builder.push_bind(&search.kind);
+    }
+    if !search.status.trim().is_empty() {
+        total_query_builder.push(" and status = ");
+        total_query_builder.push_bind(&search.status);
+        jobs_query_builder.push(" and status = ");
+        jobs_query_builder.push_bind(&search.status);
+    }
+
+    let total: (i64,) = total_query_builder.build_query_as().fetch_one(&db).await?;
+    search.per_page = if search.per_page == 0 {
+        20
+    } else {
+        search.per_page
+    };
+    search.page = max(1, min(search.page, total.0 / search.per_page + 1));
+    jobs_query_builder.push(" order by id desc");
+    jobs_query_builder.push(" limit ");
+    jobs_query_builder.push_bind(search.per_page);
+    jobs_query_builder.push(" offset ");
+    jobs_query_builder.push_bind((search.page - 1) * search.per_page);
+
+    let jobs: Vec<Job> = jobs_query_builder.build_query_as().fetch_all(&db).await?;
+
+    return ok(json!({
+        "page": search.page,
+        "per_page": search.per_page,
+        "total": total.0,
+        "jobs": jobs,
+    }));
+}
+
+//下载任务生成的文件, 文件保存在临时目录中
+pub async fn admin_job_file(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Path(id): Path<i64>,
+) -> Result<Response, AeError> {
+    let job_: Option<Job> = query_as("select * from jobs where id = ?1")
+        .bind(id)
+        .fetch_optional(&db)
+        .await?;
+    let file_name = match job_ {
+        Some(job) if job.status == "done" => from_str::<Value>(&job.result)?["file"]
+            .as_str()
+            .map(|f| f.to_string()),
+        _ => None,
+    };
+    //只允许临时目录下的文件名
+    let file_name = match file_name {
+        Some(f) if !f.contains(['/', '\\']) && !f.starts_with('.') => f,
+        _ => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("file not found"))?);
+        }
+    };
+    let file_path = PathBuf::from(&settings.tmp_dir).join(&file_name);
+    let content_type = match file_path.extension().and_then(|e| e.to_str()) {
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("json") | Some("ndjson") => "application/json",
+        Some("html") => "text/html; charset=utf-8",
+        Some("gz") => "application/gzip",
+        _ => "application/octet-stream",
+    };
+
+    //html(如拣货单)直接在浏览器打开打印
+    let disposition = if content_type.starts_with("text/html") {
+        "inline"
+    } else {
+        "attachment"
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            "Content-Disposition",
+            format!("{}; filename=\"{}\"", disposition, file_name),
+        )
+        .header("Content-Type", content_type)
+        .body(Body::from(fs::read(&file_path)?))?)
+}
+
+//定时任务列表, 包括计划、下次执行时间和最近一次执行的任务
+pub async fn admin_schedule_list(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+) -> Result<Res, AeError> {
+    let now = OffsetDateTime::now_local()?;
+    let mut tasks = vec![];
+    for (task, cron) in scheduler::schedule(&settings) {
+        let last_job: Option<Job> =
+            query_as("select * from jobs where kind = ? order by id desc limit 1")
+                .bind(task)
+                .fetch_optional(&db)
+                .await?;
+        let (cron, next_run, error) = match cron {
+            Ok(Some(cron)) => (
+                cron.expr().to_string(),
+                cron.next_after(now).and_then(|t| t.format(&Rfc3339).ok()),
+                None,
+            ),
+            Ok(None) => (String::new(), None, None),
+            Err(e) => (String::new(), None, Some(e.to_string())),
+        };
+        tasks.push(json!({
+            "task": task,
+            "cron": cron,
+            "next_run": next_run,
+            "error": error,
+            "last_job": last_job,
+        }));
+    }
+    return ok(json!(tasks));
+}
+
+//立即执行定时任务, 返回任务id
+pub async fn admin_schedule_run(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Path(task): Path<String>,
+) -> Result<Res, AeError> {
+    match scheduler::run_task(&db, &settings, &task).await {
+        Ok(id) => {
+            return ok(json!(id));
+        }
+        Err(e) => {
+            return err(e.to_string());
+        }
+    }
+}
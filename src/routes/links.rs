@@ -0,0 +1,153 @@
+use crate::links::{self, Link};
+use crate::sourcing::{self, Switch};
+use crate::types::{err, ok, AEState, AeError, Res};
+use axum::extract::{Path, Query, State};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::query_as;
+use std::collections::HashSet;
+
+#[derive(Deserialize)]
+pub struct SLReq {
+    offer_id: Option<i64>,
+    product_id: Option<i64>,
+}
+//按offer_id或product_id查关联
+pub async fn admin_link_list(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Query(search): Query<SLReq>,
+) -> Result<Res, AeError> {
+    let links: Vec<Link> = match (search.offer_id, search.product_id) {
+        (_, Some(product_id)) => links::product_links(&db, product_id).await?,
+        (Some(offer_id), None) => links::offer_links(&db, offer_id).await?,
+        (None, None) => {
+            return err("需要offer_id或product_id".to_string());
+        }
+    };
+    return ok(json!(links));
+}
+
+//添加关联, primary为1时设为主offer
+pub async fn admin_link_add(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path((offer_id, product_id, primary)): Path<(i64, i64, i64)>,
+) -> Result<Res, AeError> {
+    if offer_id <= 0 || product_id <= 0 {
+        return err("offer_id和product_id不能为0".to_string());
+    }
+    let mut db_trans = db.begin().await?;
+    let changed = links::link(&mut db_trans, offer_id, product_id, primary == 1).await?;
+    db_trans.commit().await?;
+    if changed {
+        return ok(json!(()));
+    } else {
+        return ok(json!("未改变任何数据"));
+    }
+}
+
+pub async fn admin_link_remove(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path((offer_id, product_id)): Path<(i64, i64)>,
+) -> Result<Res, AeError> {
+    let mut db_trans = db.begin().await?;
+    let changed = links::unlink(&mut db_trans, offer_id, product_id).await?;
+    db_trans.commit().await?;
+    if changed {
+        return ok(json!(()));
+    } else {
+        return ok(json!("未改变任何数据"));
+    }
+}
+
+pub async fn admin_link_check(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+) -> Result<Res, AeError> {
+    return ok(links::check(&db).await?);
+}
+
+pub async fn admin_link_repair(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+) -> Result<Res, AeError> {
+    let repaired = links::repair(&db).await?;
+    return ok(json!({
+        "repaired": repaired,
+        "check": links::check(&db).await?,
+    }));
+}
+
+//按可用性、价格、商家销量重新选择主offer, product_id为0时处理所有有关联的product
+pub async fn admin_link_reselect(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(product_id): Path<i64>,
+) -> Result<Res, AeError> {
+    let product_ids: Vec<(i64,)> = if product_id > 0 {
+        vec![(product_id,)]
+    } else {
+        query_as("select distinct product_id from offer_products")
+            .fetch_all(&db)
+            .await?
+    };
+    let mut switched = vec![];
+    let mut db_trans = db.begin().await?;
+    for (product_id,) in product_ids {
+        if let Some(to) = sourcing::reselect(
+            &mut db_trans,
+            product_id,
+            sourcing::RESELECT,
+            &json!({}),
+            &HashSet::new(),
+        )
+        .await?
+        {
+            switched.push((product_id, to));
+        }
+    }
+    db_trans.commit().await?;
+    return ok(json!(switched));
+}
+
+#[derive(Deserialize)]
+pub struct SSReq {
+    product_id: Option<i64>,
+}
+//主offer切换记录, 最近的200条
+pub async fn admin_link_switches(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Query(search): Query<SSReq>,
+) -> Result<Res, AeError> {
+    let switches: Vec<Switch> = match search.product_id {
+        Some(product_id) => {
+            query_as("select * from offer_switches where product_id=? order by id desc limit 200")
+                .bind(product_id)
+                .fetch_all(&db)
+                .await?
+        }
+        None => {
+            query_as("select * from offer_switches order by id desc limit 200")
+                .fetch_all(&db)
+                .await?
+        }
+    };
+    return ok(json!(switches));
+}
@@ -1,18 +1,28 @@
-use crate::types::{ok, AEState};
+use crate::config::SharedSettings;
+use crate::types::{err, ok, AEState, AeError, Res, ServerState};
 use axum::{
-    extract::{Json, State},
-    response::IntoResponse,
+    extract::{DefaultBodyLimit, Json, State},
     routing::{get, post},
     Router,
 };
 
-use serde_json::json;
+use serde_json::{json, to_value};
 
+mod archive;
+mod backup;
+mod crawl;
+mod dataset;
+mod jobs;
+mod links;
 mod offers;
 mod orders;
 mod products;
+mod sales;
+mod skus;
+mod suppliers;
+mod tracking;
 
-pub fn router<S>(state: AEState) -> Router<S> {
+pub fn router<S>(state: ServerState) -> Router<S> {
     Router::new()
         .route("/get/cfg", post(get_cfg))
         .nest(
@@ -21,6 +31,7 @@ pub fn router<S>(state: AEState) -> Router<S> {
                 .route("/new", post(offers::new))
                 .route("/get/:offer_id", get(offers::get))
                 .route("/next", get(offers::next))
+                .route("/fail", post(offers::fail))
                 .route("/update", post(offers::update)),
         )
         .nest(
@@ -38,16 +49,23 @@ pub fn router<S>(state: AEState) -> Router<S> {
                 .route("/get/:oid", get(orders::get_from_order_id))
                 .route("/update_or_add", post(orders::update_or_add))
                 .route("/next", get(orders::next))
+                .route("/fail", post(orders::fail))
                 .route(
                     "/:oid/update_weight/:weight/item_num/:item_num",
                     get(orders::update_weight),
                 )
-                .route("/set_lg_id", post(orders::set_lg_id)),
+                .route(
+                    "/shipments/:id/update_weight/:weight/item_num/:item_num",
+                    get(orders::update_shipment_weight),
+                )
+                .route("/set_lg_id", post(orders::set_lg_id))
+                .route("/tracking", post(tracking::tracking)),
         )
         .nest(
             "/admin",
             Router::new()
                 .route("/get/cfg", post(get_cfg))
+                .route("/reload/cfg", get(reload_cfg))
                 .nest(
                     "/offers",
                     Router::new()
@@ -57,6 +75,7 @@ pub fn router<S>(state: AEState) -> Router<S> {
                         .route("/tips", post(offers::admin_offer_tips))
                         .route("/pid/:id/:pid", get(offers::admin_offer_pid))
                         .route("/mid/:id/:mid", get(offers::admin_offer_mid))
+                        .route("/events/:offer_id", get(offers::admin_offer_events))
                         .route(
                             "/allbetterpricechnageisok",
                             get(offers::all_better_price_chnage_is_ok),
@@ -77,6 +96,7 @@ pub fn router<S>(state: AEState) -> Router<S> {
                         )
                         .route("/delete/:id/:tf", get(products::admin_product_delete))
                         .route("/tips", post(products::admin_product_tips))
+                        .route("/location", post(products::admin_product_location))
                         .route("/oid/:id/:oid", get(products::admin_product_oid))
                         .route(
                             "/clear_stock_info/:id",
@@ -92,11 +112,116 @@ pub fn router<S>(state: AEState) -> Router<S> {
                             get(products::admin_product_dl_discount_xslx),
                         )
                         .route("/upload_xlsx", post(products::admin_product_upload_xlsx))
-                        .route("/available", get(products::admin_product_available)),
+                        .route(
+                            "/upload_xlsx/commit/:token",
+                            get(products::admin_product_upload_commit),
+                        )
+                        .route("/available", get(products::admin_product_available))
+                        .route(
+                            "/recompute_traffic",
+                            get(products::admin_product_recompute_traffic),
+                        ),
                 )
                 .nest(
                     "/orders",
-                    Router::new().route("/show", post(orders::admin_order_show)),
+                    Router::new()
+                        .route("/show", post(orders::admin_order_show))
+                        .route("/picking", post(orders::admin_order_picking))
+                        .route("/shipments/:order_id", get(orders::admin_order_shipments))
+                        .route("/notes/:order_id", get(orders::admin_order_notes))
+                        .route("/notes/add", post(orders::admin_order_add_note))
+                        .route(
+                            "/attention/:order_id/:tf",
+                            get(orders::admin_order_attention),
+                        ),
+                )
+                .nest(
+                    "/jobs",
+                    Router::new()
+                        .route("/show", post(jobs::admin_job_show))
+                        .route("/get/:id", get(jobs::admin_job_get))
+                        .route("/file/:id", get(jobs::admin_job_file)),
+                )
+                .nest(
+                    "/suppliers",
+                    Router::new()
+                        .route("/show", post(suppliers::admin_supplier_show))
+                        .route(
+                            "/blacklist/:id/:tf",
+                            get(suppliers::admin_supplier_blacklist),
+                        )
+                        .route("/offers/:id", get(suppliers::admin_supplier_offers)),
+                )
+                .nest(
+                    "/links",
+                    Router::new()
+                        .route("/list", get(links::admin_link_list))
+                        .route(
+                            "/add/:offer_id/:product_id/:primary",
+                            get(links::admin_link_add),
+                        )
+                        .route(
+                            "/remove/:offer_id/:product_id",
+                            get(links::admin_link_remove),
+                        )
+                        .route("/check", get(links::admin_link_check))
+                        .route("/repair", get(links::admin_link_repair))
+                        .route("/reselect/:product_id", get(links::admin_link_reselect))
+                        .route("/switches", get(links::admin_link_switches)),
+                )
+                .nest(
+                    "/sales",
+                    Router::new().route("/stats", get(sales::admin_sales_stats)),
+                )
+                .nest(
+                    "/skus",
+                    Router::new()
+                        .route("/list/:product_id", get(skus::admin_sku_list))
+                        .route("/set", post(skus::admin_sku_set))
+                        .route(
+                            "/suggest/:product_id/:offer_id",
+                            get(skus::admin_sku_suggest),
+                        )
+                        .route("/delete/:id", get(skus::admin_sku_delete)),
+                )
+                .nest(
+                    "/tracking",
+                    Router::new()
+                        .route("/events/:order_id", get(tracking::admin_tracking_events))
+                        .route("/stuck", get(tracking::admin_tracking_stuck))
+                        .route("/transit", get(tracking::admin_tracking_transit)),
+                )
+                .nest(
+                    "/archive",
+                    Router::new().route("/search", post(archive::admin_archive_search)),
+                )
+                .nest(
+                    "/backup",
+                    Router::new()
+                        .route("/list", get(backup::admin_backup_list))
+                        .route("/create", get(backup::admin_backup_create))
+                        .route("/restore", post(backup::admin_backup_restore)),
+                )
+                .nest(
+                    "/data",
+                    Router::new()
+                        .route("/export", get(dataset::admin_data_export))
+                        //导入文件可能较大, 不使用默认的2MB限制
+                        .route(
+                            "/import",
+                            post(dataset::admin_data_import)
+                                .layer(DefaultBodyLimit::max(512 * 1024 * 1024)),
+                        ),
+                )
+                .nest(
+                    "/crawl",
+                    Router::new().route("/status", get(crawl::admin_crawl_status)),
+                )
+                .nest(
+                    "/schedule",
+                    Router::new()
+                        .route("/list", get(jobs::admin_schedule_list))
+                        .route("/run/:task", get(jobs::admin_schedule_run)),
                 ),
         )
         .with_state(state)
@@ -108,10 +233,26 @@ async fn get_cfg(
         settings,
     }): State<AEState>,
     Json(keys): Json<Vec<String>>,
-) -> impl IntoResponse {
+) -> Result<Res, AeError> {
+    let settings = to_value(&*settings)?;
     let mut result = json!({});
     for k in keys.iter() {
         result[k] = settings[k].clone();
     }
-    ok(result)
+    return ok(result);
+}
+
+//重新读取配置文件中的settings, 配置文件修改后也会自动重新加载
+async fn reload_cfg(State(settings): State<SharedSettings>) -> Result<Res, AeError> {
+    match settings.reload() {
+        Ok(changed) if changed.is_empty() => {
+            return ok(json!("未改变任何数据"));
+        }
+        Ok(changed) => {
+            return ok(json!({ "changed": changed }));
+        }
+        Err(e) => {
+            return err(e.to_string());
+        }
+    }
 }
@@ -1,13 +1,18 @@
+use crate::leases::{self, LeaseConf, LeaseFail};
+use crate::links;
 use crate::models::{NewOffer, Offer, Product};
+use crate::normalize::Normalizer;
+use crate::skus;
+use crate::sourcing;
 use crate::types::{err, ok, AEState, AeError, Res};
-use anyhow::anyhow;
-use axum::extract::{Json, Path, State};
+use axum::extract::{Json, Path, Query, State};
 use regex::Regex;
-use serde::Deserialize;
-use serde_json::{from_str, json, Value};
-use sqlx::{query, query_as, QueryBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json};
+use sqlx::{query, query_as, FromRow, QueryBuilder, SqlitePool};
 use std::cmp::{max, min};
-use time::{Duration, OffsetDateTime};
+use std::collections::HashSet;
+use time::{format_description::well_known::Rfc3339, serde::rfc3339 as show_time, OffsetDateTime};
 
 pub async fn new(
     State(AEState {
@@ -24,9 +29,10 @@ pub async fn new(
         return err("该offer_id已存在".to_string());
     }
 
-    let offer_price_rate: f64 = settings["OFFER_PRICE_RATE"].as_f64().unwrap_or(1.5);
+    let offer_price_rate: f64 = settings.offer_price_rate;
     //价格按倍率调整
     no.price = (no.price as f64 * offer_price_rate) as i64;
+    no.sale_info = Normalizer::from_settings(&settings).offer_sale_info(&no.sale_info)?;
     let offer = Offer::new(&no);
 
     let id = query("INSERT INTO offers (product_id, sale_record, discount, sku_info_use, detail_url_use, pending, tips, created_at, updated_at, deleted_at, offer_id, title, cover, wireless_video_id, detail_video_id, model_id, sale30, sale_info, price, better_price, sku_info, detail_url, supplier, store_url, promotion_end) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25)")
@@ -78,22 +84,29 @@ pub async fn get(
             .fetch_optional(&db)
             .await?;
         if let Some(pd) = pd_ {
-            let advise_stock_num =
-                (pd.sales30 as f64) * settings["SALE2STOCK"].as_f64().unwrap_or(0.67);
-            let sale_info: Value = from_str(&pd.sale_info.to_uppercase())?; //已卖出数据为基准
-            let stock_info: Value = from_str(&pd.stock_info.to_uppercase())?;
+            let advise_stock_num = (pd.sales30 as f64) * settings.sale2stock;
+            let norm = Normalizer::from_settings(&settings);
+            let sale_info = norm.sku_map(&from_str(&pd.sale_info)?); //已卖出数据为基准
+            let stock_info = norm.sku_map(&from_str(&pd.stock_info)?);
             let mut advise_stock = json!({});
+            let maps =
+                skus::by_sku(skus::mappings(&db, &norm, pd.product_id, offer.offer_id).await?);
             let reg = Regex::new(r"[^\d]")?;
             for (color, sizes) in sale_info.as_object().unwrap() {
                 let color_idx = reg.replace_all(color, "").to_string();
                 advise_stock[&color_idx]["color_name"] = json!(color);
                 for (size, sold) in sizes.as_object().unwrap() {
-                    let short_size = size
-                        .replace("XXXXXL", "5XL")
-                        .replace("XXXXL", "4XL")
-                        .replace("XXXL", "3XL")
-                        .replace("XXL", "2XL");
-                    advise_stock[&color_idx][short_size] = json!(if pd.sale_count > 0 {
+                    //有sku对应关系时用1688的颜色和尺码
+                    let mut size_key = size.clone();
+                    if let Some(m) = maps.get(&(color.clone(), size.clone())) {
+                        if !m.offer_color.is_empty() {
+                            advise_stock[&color_idx]["offer_color"] = json!(m.offer_color);
+                        }
+                        if !m.offer_size.is_empty() {
+                            size_key = m.offer_size.clone();
+                        }
+                    }
+                    advise_stock[&color_idx][size_key] = json!(if pd.sale_count > 0 {
                         let advise =
                             (sold.as_f64().unwrap() * advise_stock_num) / (pd.sale_count as f64);
                         let stock = stock_info[color][size].as_f64().unwrap();
@@ -113,30 +126,147 @@ pub async fn get(
     }
 }
 
+#[derive(Deserialize)]
+pub struct NextReq {
+    worker: Option<String>,
+    count: Option<i64>,
+}
+//租用一批需要更新的offer, 多个采集端同时请求时不会重复
+//不传count时只返回一个链接
 pub async fn next(
     State(AEState {
         db_pool: db,
         settings,
     }): State<AEState>,
+    Query(req): Query<NextReq>,
 ) -> Result<Res, AeError> {
-    let row: Option<(i64,)> = query_as("select offer_id from offers where updated_at<?1 and deleted_at is null order by id asc limit 1").bind(OffsetDateTime::now_local()?.date()).fetch_optional(&db).await?;
-    if let Some(r) = row {
-        if let Some(offer_url_pattern) = settings["OFFER_URL_PATTERN"].as_str() {
-            return ok(json!(
-                offer_url_pattern.replace("{OFFER_ID}", &r.0.to_string())
-            ));
-        } else {
-            return Err(anyhow!("no offer_url_pattern").into());
+    let offer_url_pattern = settings.offer_url_pattern.as_str();
+    let count = req.count.unwrap_or(1).clamp(1, leases::MAX_BATCH);
+    let worker = req.worker.unwrap_or_default();
+    let conf = LeaseConf::from_settings(&settings);
+    let now = leases::now()?;
+    //未失败过的优先, 其次是更新时间最早的
+    let rows: Vec<(i64,)> = query_as("select o.offer_id from offers o left join leases l on l.kind=?1 and l.item_id=o.offer_id where o.updated_at<?2 and o.deleted_at is null and o.unavailable_at is null and (l.id is null or l.available_at<=?3) order by coalesce(l.attempts,0) asc, o.updated_at asc, o.id asc limit ?4")
+        .bind(leases::OFFER)
+        .bind(now.date())
+        .bind(now)
+        .bind(count * 2)
+        .fetch_all(&db)
+        .await?;
+    let mut items = vec![];
+    for (offer_id,) in rows {
+        if items.len() as i64 >= count {
+            break;
         }
-    } else {
-        //删除180天前废弃的offer
-        query("delete from offers where deleted_at is not null and deleted_at < ?")
-            .bind(OffsetDateTime::now_local()?.date() - Duration::days(180))
-            .execute(&db)
+        if let Some(expires_at) =
+            leases::claim(&db, &conf, leases::OFFER, offer_id, &worker).await?
+        {
+            items.push(json!({
+                "id": offer_id,
+                "url": offer_url_pattern.replace("{OFFER_ID}", &offer_id.to_string()),
+                "expires_at": expires_at.format(&Rfc3339)?,
+            }));
+        }
+    }
+    if items.is_empty() {
+        return err("all done".to_string());
+    }
+    if req.count.is_none() {
+        return ok(items[0]["url"].clone());
+    }
+    return ok(json!(items));
+}
+
+//采集失败, 稍后重试
+//连续失败达到OFFER_FAIL_THRESHOLD次判定为失效, 不再采集, 并标记对应的product
+//没有租用时(重复提交或租约已过期)不计入失败次数
+pub async fn fail(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Json(lf): Json<LeaseFail>,
+) -> Result<Res, AeError> {
+    let conf = LeaseConf::from_settings(&settings);
+    if !leases::fail(&db, &conf, leases::OFFER, lf.id, &lf.error).await? {
+        return err("not leased".to_string());
+    }
+
+    let threshold = settings.offer_fail_threshold;
+    let now = OffsetDateTime::now_local()?;
+    let mut db_trans = db.begin().await?;
+    let row: Option<(i64, Option<OffsetDateTime>)> = query_as("update offers set fail_count=fail_count+1,fail_reason=? where offer_id=? returning fail_count,unavailable_at")
+        .bind(&lf.error)
+        .bind(lf.id)
+        .fetch_optional(&mut *db_trans)
+        .await?;
+    let (fail_count, unavailable_at) = match row {
+        Some(r) => r,
+        None => {
+            return err("not found".to_string());
+        }
+    };
+    let unavailable = unavailable_at.is_some() || fail_count >= threshold;
+    if unavailable_at.is_none() && unavailable {
+        query("update offers set unavailable_at=? where offer_id=?")
+            .bind(now)
+            .bind(lf.id)
+            .execute(&mut *db_trans)
             .await?;
+        query("insert into offer_events (offer_id,kind,detail,created_at) values (?,'unavailable',?,?)")
+            .bind(lf.id)
+            .bind(json!({"fail_count": fail_count, "reason": &lf.error}).to_string())
+            .bind(now)
+            .execute(&mut *db_trans)
+            .await?;
+        query("update products set offer_unavailable=1 where offer_id=?")
+            .bind(lf.id)
+            .execute(&mut *db_trans)
+            .await?;
+    }
+    db_trans.commit().await?;
 
-        return err("all done".to_string());
+    //失效后切换到备用offer
+    let mut switched = vec![];
+    if unavailable_at.is_none() && unavailable {
+        switched = sourcing::on_offer_change(
+            &db,
+            lf.id,
+            sourcing::UNAVAILABLE,
+            json!({"fail_count": fail_count, "reason": &lf.error}),
+            &HashSet::new(),
+        )
+        .await?;
+    }
+
+    return ok(json!({
+        "fail_count": fail_count,
+        "unavailable": unavailable,
+        "switched": switched,
+    }));
+}
+
+//采集成功, 清除失败记录, 已失效的恢复
+async fn offer_available(db: &SqlitePool, offer: &Offer) -> anyhow::Result<()> {
+    let mut db_trans = db.begin().await?;
+    query("update offers set fail_count=0,fail_reason='',unavailable_at=null where offer_id=?")
+        .bind(offer.offer_id)
+        .execute(&mut *db_trans)
+        .await?;
+    if offer.unavailable_at.is_some() {
+        query("insert into offer_events (offer_id,kind,detail,created_at) values (?,'available',?,?)")
+            .bind(offer.offer_id)
+            .bind(json!({"unavailable_at": offer.unavailable_at.map(|t| t.format(&Rfc3339)).transpose()?}).to_string())
+            .bind(OffsetDateTime::now_local()?)
+            .execute(&mut *db_trans)
+            .await?;
+        query("update products set offer_unavailable=0 where offer_id=?")
+            .bind(offer.offer_id)
+            .execute(&mut *db_trans)
+            .await?;
     }
+    db_trans.commit().await?;
+    Ok(())
 }
 
 pub async fn update(
@@ -144,15 +274,26 @@ pub async fn update(
         db_pool: db,
         settings,
     }): State<AEState>,
-    Json(no): Json<NewOffer>,
+    Json(mut no): Json<NewOffer>,
 ) -> Result<Res, AeError> {
     let offer_: Option<Offer> = query_as("SELECT * FROM offers WHERE offer_id = ?1")
         .bind(no.offer_id)
         .fetch_optional(&db)
         .await?;
-    if let Some(old_offer) = offer_ {
-        let updated_offer = old_offer.update(&no, settings);
-        let affacted_rows = query("UPDATE offers SET sale_record = ?,title = ?, cover = ?, wireless_video_id = ?, detail_video_id = ?, sale30 = ?, sale_info = ?, detail_url = ?, better_price = ?, discount = ?, pending = ?, tips = ?, sku_info = ?, supplier = ?, store_url = ?, promotion_end = ?, updated_at = ? WHERE offer_id = ?")
+    if let Some(mut old_offer) = offer_ {
+        //新旧数据按同样的名称合并销量
+        let norm = Normalizer::from_settings(&settings);
+        no.sale_info = norm.offer_sale_info(&no.sale_info)?;
+        old_offer.sale_info = norm.offer_sale_info(&old_offer.sale_info)?;
+        old_offer.sale_record = norm.offer_sale_record(&old_offer.sale_record)?;
+        let old_price = old_offer.better_price;
+        //上次有库存, 这次没有了的sku
+        let lost_skus: HashSet<String> = sourcing::stock_skus(&old_offer.sale_record)
+            .difference(&sourcing::in_stock(&from_str(&no.sale_info)?))
+            .cloned()
+            .collect();
+        let mut updated_offer = old_offer.update(&no, &settings);
+        let affacted_rows = query("UPDATE offers SET sale_record = ?,title = ?, cover = ?, wireless_video_id = ?, detail_video_id = ?, sale30 = ?, sale_info = ?, detail_url = ?, better_price = ?, discount = ?, pending = ?, tips = ?, sku_info = ?, supplier = ?, store_url = ?, promotion_end = ?, updated_at = ?, price_changes = ?, sku_changes = ? WHERE offer_id = ?")
         .bind(&updated_offer.sale_record)
         .bind(&updated_offer.title)
         .bind(&updated_offer.cover)
@@ -170,9 +311,39 @@ pub async fn update(
         .bind(&updated_offer.store_url)
         .bind(updated_offer.promotion_end)
         .bind(updated_offer.updated_at)
+        .bind(updated_offer.price_changes)
+        .bind(updated_offer.sku_changes)
         .bind(updated_offer.offer_id)
         .execute(&db).await?.rows_affected();
         if affacted_rows > 0 {
+            leases::release(&db, leases::OFFER, updated_offer.offer_id).await?;
+            if updated_offer.fail_count > 0 || updated_offer.unavailable_at.is_some() {
+                offer_available(&db, &updated_offer).await?;
+                updated_offer.fail_count = 0;
+                updated_offer.fail_reason = String::new();
+                updated_offer.unavailable_at = None;
+            }
+            //主offer提价或缺货时, 切换到更合适的备用offer
+            if updated_offer.better_price > old_price {
+                sourcing::on_offer_change(
+                    &db,
+                    updated_offer.offer_id,
+                    sourcing::PRICE_RAISED,
+                    json!({"old_price": old_price, "new_price": updated_offer.better_price}),
+                    &HashSet::new(),
+                )
+                .await?;
+            }
+            if !lost_skus.is_empty() {
+                sourcing::on_offer_change(
+                    &db,
+                    updated_offer.offer_id,
+                    sourcing::SKU_LOST,
+                    json!({ "skus": &lost_skus }),
+                    &lost_skus,
+                )
+                .await?;
+            }
             return ok(json!(updated_offer));
         } else {
             return err("nothing changed".to_string());
@@ -192,6 +363,8 @@ pub struct SOReq {
     supplier: String,
     pending: i64,
     deleted: bool,
+    #[serde(default)]
+    unavailable: bool,
 }
 pub async fn admin_offers_show(
     State(AEState {
@@ -233,6 +406,10 @@ pub async fn admin_offers_show(
         offers_query_builder.push(" and pending = ");
         offers_query_builder.push_bind(search.pending);
     }
+    if search.unavailable {
+        total_query_builder.push(" and unavailable_at is not null");
+        offers_query_builder.push(" and unavailable_at is not null");
+    }
     if search.deleted {
         total_query_builder.push(" and deleted_at is not null");
         offers_query_builder.push(" and deleted_at is not null");
@@ -316,12 +493,20 @@ pub async fn admin_offer_delete(
     Path((id, tf)): Path<(i64, bool)>,
 ) -> Result<Res, AeError> {
     let affected_rows = if tf {
-        query("update offers set deleted_at=? where id=?")
-            .bind(OffsetDateTime::now_local().unwrap())
-            .bind(id)
-            .execute(&db)
-            .await?
-            .rows_affected()
+        let deleted: Option<(i64,)> =
+            query_as("update offers set deleted_at=? where id=? returning offer_id")
+                .bind(OffsetDateTime::now_local().unwrap())
+                .bind(id)
+                .fetch_optional(&db)
+                .await?;
+        //删除的是主offer时切换到备用offer
+        if let Some((offer_id,)) = deleted {
+            sourcing::on_offer_change(&db, offer_id, sourcing::DELETED, json!({}), &HashSet::new())
+                .await?;
+            1
+        } else {
+            0
+        }
     } else {
         query("update offers set deleted_at=null where id=?")
             .bind(id)
@@ -369,14 +554,25 @@ pub async fn admin_offer_pid(
     }): State<AEState>,
     Path((id, pid)): Path<(i64, i64)>,
 ) -> Result<Res, AeError> {
-    if query("update offers set product_id=? where id=?")
-        .bind(pid)
+    let offer_: Option<(i64,)> = query_as("select offer_id from offers where id=?")
         .bind(id)
-        .execute(&db)
-        .await?
-        .rows_affected()
-        > 0
-    {
+        .fetch_optional(&db)
+        .await?;
+    let offer_id = match offer_ {
+        Some((offer_id,)) => offer_id,
+        None => {
+            return err("not found".to_string());
+        }
+    };
+    //pid为0时删除该offer的所有关联, 否则添加关联, 该product没有主offer时作为主offer
+    let mut db_trans = db.begin().await?;
+    let changed = if pid == 0 {
+        links::unlink_offer(&mut db_trans, offer_id).await?
+    } else {
+        links::link(&mut db_trans, offer_id, pid, false).await?
+    };
+    db_trans.commit().await?;
+    if changed {
         return ok(json!(()));
     } else {
         return ok(json!("未改变任何数据"));
@@ -423,3 +619,28 @@ pub async fn all_low_sales_is_ok(
     query("update offers set pending=0,tips=\"\" where pending=-1 and deleted_at is null and tips=\"销量低下架否?;\"").execute(&db).await?;
     return ok(json!(()));
 }
+
+#[derive(Serialize, FromRow)]
+pub struct OfferEvent {
+    id: i64,
+    offer_id: i64,
+    kind: String,
+    detail: String,
+    #[serde(with = "show_time")]
+    created_at: OffsetDateTime,
+}
+//offer的失效/恢复记录
+pub async fn admin_offer_events(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(offer_id): Path<i64>,
+) -> Result<Res, AeError> {
+    let events: Vec<OfferEvent> =
+        query_as("select * from offer_events where offer_id=? order by id desc")
+            .bind(offer_id)
+            .fetch_all(&db)
+            .await?;
+    return ok(json!(events));
+}
@@ -1,14 +1,23 @@
+use crate::analytics;
+use crate::config::Settings;
+use crate::jobs;
+use crate::leases::{self, LeaseConf};
 use crate::models::{NewOrder, Order, Product};
+use crate::normalize::Normalizer;
+use crate::order_items::{self, OrderItem};
+use crate::order_notes;
+use crate::picking;
+use crate::shipments::{self, Shipment};
+use crate::skus::{self, SkuMap};
 use crate::types::{err, ok, AEState, AeError, Res};
-use anyhow::anyhow;
-use axum::extract::{Json, Path, State};
+use axum::extract::{Json, Path, Query, State};
 use regex::Regex;
 use serde::Deserialize;
 use serde_json::{from_str, json, to_string_pretty, Value};
-use sqlx::{query, query_as, QueryBuilder};
+use sqlx::{query, query_as, QueryBuilder, SqlitePool};
 use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
-use time::{Duration, OffsetDateTime};
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
 use tracing::error;
 
 pub async fn get_from_order_id(
@@ -32,7 +41,7 @@ pub async fn get_from_order_id(
 pub async fn update_or_add(
     State(AEState {
         db_pool: db,
-        settings: _,
+        settings,
     }): State<AEState>,
     Json(patch_orders): Json<HashMap<String, NewOrder>>,
 ) -> Result<Res, AeError> {
@@ -72,6 +81,8 @@ pub async fn update_or_add(
             error!("orders未更新, 请手动检查: order_id: {}", order.order_id);
             return err("orders未更新, 请手动检查".to_string());
         }
+        //orders.remark为最新的备注, 历史备注记录在order_notes
+        order_notes::add_remark(&mut db_trans, order.order_id, &order.remark).await?;
     }
 
     let new_orders: Vec<Order> = order_ids
@@ -104,40 +115,43 @@ pub async fn update_or_add(
             return err("orders未添加, 请手动检查！".to_string());
         }
 
-        for order in new_orders {
-            let pds: Value = from_str(&order.products)?;
-            for pd in pds.as_object().unwrap().iter() {
-                let pid = pd.0.parse::<i64>()?;
-                let product_: Option<Product> =
-                    query_as("select * from products where product_id = ?")
-                        .bind(pid)
-                        .fetch_optional(&db)
-                        .await?;
-                if product_.is_none() {
-                    continue;
-                }
-                let product = product_.unwrap();
-
-                let mut sale_info: Value = from_str(&product.sale_info.to_uppercase())?;
-                let mut sale_count = product.sale_count;
-                for sku in pd.1.as_array().unwrap() {
-                    let pointer = &format!(
-                        "/{}",
-                        sku[0].as_str().unwrap().replace(" + ", "/").to_uppercase()
-                    );
-                    let num = sku[1].as_i64().unwrap();
-                    sale_info.pointer_mut(pointer).map(|x| {
-                        *x = (x.as_i64().unwrap() + num).into();
-                    });
-                    sale_count += num;
-                }
-                query("update products set sale_count=?,sale_info=? where id=?")
-                    .bind(sale_count)
-                    .bind(to_string_pretty(&sale_info)?)
-                    .bind(product.id)
-                    .execute(&mut *db_trans)
-                    .await?;
+        for order in new_orders.iter() {
+            order_items::insert(&mut db_trans, order).await?;
+            order_notes::add_remark(&mut db_trans, order.order_id, &order.remark).await?;
+        }
+
+        //按商品汇总新订单的销量
+        let new_order_ids: Vec<i64> = new_orders.iter().map(|o| o.order_id).collect();
+        let mut sales: HashMap<i64, Vec<OrderItem>> = HashMap::new();
+        for item in order_items::of_orders(&mut db_trans, &new_order_ids).await? {
+            sales.entry(item.product_id).or_default().push(item);
+        }
+        let norm = Normalizer::from_settings(&settings);
+        for (pid, items) in sales {
+            let product_: Option<Product> = query_as("select * from products where product_id = ?")
+                .bind(pid)
+                .fetch_optional(&mut *db_trans)
+                .await?;
+            if product_.is_none() {
+                continue;
+            }
+            let product = product_.unwrap();
+
+            let mut sale_info = norm.sku_map(&from_str(&product.sale_info)?);
+            let mut sale_count = product.sale_count;
+            for item in items {
+                //sale_info中还没有的sku也记录, 不再忽略
+                let (color, size) = norm.sku(&item.sku);
+                let sold = sale_info[&color][&size].as_i64().unwrap_or(0);
+                sale_info[&color][&size] = json!(sold + item.qty);
+                sale_count += item.qty;
             }
+            query("update products set sale_count=?,sale_info=? where id=?")
+                .bind(sale_count)
+                .bind(to_string_pretty(&sale_info)?)
+                .bind(product.id)
+                .execute(&mut *db_trans)
+                .await?;
         }
     }
 
@@ -147,99 +161,179 @@ pub async fn update_or_add(
     return ok(json!(exist_orders));
 }
 
+#[derive(Deserialize)]
+pub struct NextReq {
+    worker: Option<String>,
+    count: Option<i64>,
+}
+//租用一批需要统计重量的物流包裹, 多个采集端同时请求时不会重复
+//id仍为订单id, 只有一个待统计包裹时可调用 /orders/:id/update_weight
+//shipment_id为包裹id, 分包裹统计时调用 /orders/shipments/:shipment_id/update_weight
+//不传count时只返回一个链接
 pub async fn next(
     State(AEState {
         db_pool: db,
         settings,
     }): State<AEState>,
+    Query(req): Query<NextReq>,
 ) -> Result<Res, AeError> {
-    let order_: Option<(Option<String>,)> = query_as("select lg_order_id from orders where created_at between ?1 and ?2 and updated_at < ?3 and weight = 0 and lg_order_id is not null order by id asc limit 1")
-    .bind(OffsetDateTime::now_local()?.date() - Duration::days(60))
-    .bind(OffsetDateTime::now_local()?.date() - Duration::days(3))
-    .bind(OffsetDateTime::now_local()?.date())
-    .fetch_optional(&db).await?;
-    if let Some((Some(lg_order_id),)) = order_ {
-        if let Some(lg_order_url_pattern) = settings["LG_ORDER_URL_PATTERN"].as_str() {
-            return ok(json!(
-                lg_order_url_pattern.replace("{LG_ORDER_ID}", &lg_order_id)
-            ));
-        } else {
-            return Err(anyhow!("no lg_order_url_pattern").into());
+    let lg_order_url_pattern = settings.lg_order_url_pattern.as_str();
+    let count = req.count.unwrap_or(1).clamp(1, leases::MAX_BATCH);
+    let worker = req.worker.unwrap_or_default();
+    let conf = LeaseConf::from_settings(&settings);
+    let now = leases::now()?;
+    //未失败过的优先, 其次是最早的订单
+    let rows: Vec<(i64, i64, String)> = query_as("select s.id, s.order_id, s.lg_order_id from shipments s join orders o on o.order_id=s.order_id left join leases l on l.kind=?1 and l.item_id=s.id where o.created_at between ?2 and ?3 and s.updated_at < ?4 and s.weight = 0 and s.lg_order_id != '' and (l.id is null or l.available_at<=?5) order by coalesce(l.attempts,0) asc, o.created_at asc, s.id asc limit ?6")
+        .bind(leases::SHIPMENT)
+        .bind(now.date() - Duration::days(60))
+        .bind(now.date() - Duration::days(3))
+        .bind(now.date())
+        .bind(now)
+        .bind(count * 2)
+        .fetch_all(&db)
+        .await?;
+    let mut items = vec![];
+    for (id, order_id, lg_order_id) in rows {
+        if items.len() as i64 >= count {
+            break;
         }
-    } else {
-        //删除180天前的订单
-        query("delete from orders where created_at < ?")
-            .bind(OffsetDateTime::now_local()?.date() - Duration::days(180))
-            .execute(&db)
-            .await?;
-
+        if let Some(expires_at) = leases::claim(&db, &conf, leases::SHIPMENT, id, &worker).await? {
+            items.push(json!({
+                "id": order_id,
+                "shipment_id": id,
+                "order_id": order_id,
+                "lg_order_id": lg_order_id,
+                "url": lg_order_url_pattern.replace("{LG_ORDER_ID}", &lg_order_id),
+                "expires_at": expires_at.format(&Rfc3339)?,
+            }));
+        }
+    }
+    if items.is_empty() {
         return err("all done".to_string());
     }
+    if req.count.is_none() {
+        return ok(items[0]["url"].clone());
+    }
+    return ok(json!(items));
+}
+
+#[derive(Deserialize)]
+pub struct ShipmentFail {
+    id: i64,
+    #[serde(default)]
+    shipment_id: Option<i64>,
+    #[serde(default)]
+    error: String,
+}
+//获取物流信息失败, 稍后重试, id为订单id, 有shipment_id时只处理该包裹, 否则处理该订单已租用的包裹
+pub async fn fail(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Json(sf): Json<ShipmentFail>,
+) -> Result<Res, AeError> {
+    let conf = LeaseConf::from_settings(&settings);
+    let ids: Vec<i64> = match sf.shipment_id {
+        Some(id) => vec![id],
+        None => query_as::<_, (i64,)>("select l.item_id from leases l join shipments s on s.id=l.item_id where l.kind=? and s.order_id=?")
+            .bind(leases::SHIPMENT)
+            .bind(sf.id)
+            .fetch_all(&db)
+            .await?
+            .into_iter()
+            .map(|r| r.0)
+            .collect(),
+    };
+    let mut failed = false;
+    for id in ids {
+        failed |= leases::fail(&db, &conf, leases::SHIPMENT, id, &sf.error).await?;
+    }
+    if failed {
+        return ok(json!("retry later"));
+    } else {
+        return err("not leased".to_string());
+    }
 }
 
 #[derive(Deserialize)]
 pub struct UpOdLg {
     order_id: i64,
     lg_order_id: String,
+    //包裹内的订单行 [[line_id, qty]], 不传为整单
+    #[serde(default)]
+    items: Vec<(i64, i64)>,
 }
 pub async fn set_lg_id(
     State(AEState {
         db_pool: db,
-        settings: _,
+        settings,
     }): State<AEState>,
     Json(sets): Json<Vec<UpOdLg>>,
 ) -> Result<Res, AeError> {
     for o in sets.iter() {
         //不用考虑更新失败，因为重入页面不符合“where”更新条件，不会发生更新
+        //orders.lg_order_id只保留最大的一个用于显示, 每个物流单号记录为一个包裹
         query("update orders set lg_order_id=? where order_id=? and (lg_order_id is null or lg_order_id<?)").bind(&o.lg_order_id).bind(o.order_id).bind(&o.lg_order_id).execute(&db).await?;
+        if !o.lg_order_id.is_empty() {
+            let mut db_trans = db.begin().await?;
+            //整单的物流单号更换时替换原来的包裹, 分包裹的是新增的包裹
+            if o.items.is_empty()
+                && !shipments::supersede(&mut db_trans, o.order_id, &o.lg_order_id).await?
+            {
+                continue;
+            }
+            shipments::add(&mut db_trans, o.order_id, &o.lg_order_id, &o.items).await?;
+            db_trans.commit().await?;
+        }
     }
 
-    let query_str = format!(
-        "select order_id, products from orders where order_id in ({})",
-        vec!["?"; sets.len()].join(",")
-    );
-    let mut query_ = query_as(&query_str);
-    for o in sets.iter() {
-        query_ = query_.bind(o.order_id);
-    }
-    let orders: Vec<(i64, String)> = query_.fetch_all(&db).await?;
+    let order_ids: Vec<i64> = sets.iter().map(|o| o.order_id).collect();
+    let items = order_items::of_orders(&mut *db.acquire().await?, &order_ids).await?;
 
     let mut pids: HashSet<i64> = HashSet::new();
     let mut line_pds: HashMap<i64, (i64, String, String)> = HashMap::new();
-    for o in orders.iter() {
-        let pds: HashMap<i64, Vec<(String, i64, i64)>> = from_str(&o.1)?;
-        pids.extend(pds.keys());
-        for (pid, pd) in pds {
-            for p in pd {
-                line_pds.insert(p.2, (pid, "".to_string(), p.0));
-            }
-        }
+    for item in items {
+        pids.insert(item.product_id);
+        line_pds.insert(item.line_id, (item.product_id, "".to_string(), item.sku));
     }
 
     let query_str = format!(
-        "select p.product_id, o.model_id, o.sku_info_use from products p left join offers o on p.offer_id = o.offer_id where p.product_id in ({})",
+        "select p.product_id, o.model_id, o.sku_info_use, p.offer_id from products p left join offers o on p.offer_id = o.offer_id where p.product_id in ({})",
         vec!["?"; pids.len()].join(",")
     );
     let mut query_ = query_as(&query_str);
     for pid in pids {
         query_ = query_.bind(pid);
     }
-    let pds: Vec<(i64, String, String)> = query_.fetch_all(&db).await?;
+    let pds: Vec<(i64, String, String, i64)> = query_.fetch_all(&db).await?;
 
-    let mut pd_ofs: HashMap<i64, (String, Value)> = HashMap::new();
+    let mut pd_ofs: HashMap<i64, (String, Value, SkuMap)> = HashMap::new();
+    let norm = Normalizer::from_settings(&settings);
     for pd in pds {
         let colors: Value = from_str(&pd.2)?;
-        pd_ofs.insert(pd.0, (pd.1, colors["skuProps"][0]["value"].to_owned()));
+        let maps = skus::by_sku(skus::mappings(&db, &norm, pd.0, pd.3).await?);
+        pd_ofs.insert(
+            pd.0,
+            (pd.1, colors["skuProps"][0]["value"].to_owned(), maps),
+        );
     }
 
     let reg = Regex::new(r"[^\d]")?;
     for line in line_pds.values_mut() {
         if let Some(pd) = pd_ofs.get(&line.0) {
+            line.1 = pd.0.clone();
+            //优先用sku对应关系, 没有对应的再按颜色序号
+            if let Some(m) = pd.2.get(&norm.sku(&line.2)) {
+                if !m.offer_color.is_empty() {
+                    line.2 = m.offer_color.clone();
+                    continue;
+                }
+            }
             let color_idx: usize = reg
                 .replace_all(line.2.split(" + ").next().unwrap(), "")
                 .parse::<usize>()?
                 - 1;
-            line.1 = pd.0.clone();
             line.2 = pd.1[color_idx]["name"].as_str().unwrap_or("").to_string();
         }
     }
@@ -247,6 +341,7 @@ pub async fn set_lg_id(
     return ok(json!(line_pds));
 }
 
+//按订单更新重量, 只能用于只有一个待统计包裹的订单
 pub async fn update_weight(
     State(AEState {
         db_pool: db,
@@ -254,74 +349,108 @@ pub async fn update_weight(
     }): State<AEState>,
     Path((oid, weight, item_num)): Path<(i64, i64, i64)>,
 ) -> Result<Res, AeError> {
-    let order_: Option<Order> = query_as("SELECT * FROM orders WHERE order_id = ?1")
+    let ids: Vec<(i64,)> = query_as("select id from shipments where order_id=? and weight=0")
         .bind(oid)
-        .fetch_optional(&db)
+        .fetch_all(&db)
         .await?;
-    if order_.is_none() {
-        return err("未找到该订单".to_string());
+    match ids.len() {
+        0 => {
+            return err("未找到该订单待统计的包裹".to_string());
+        }
+        1 => {
+            return update_shipment(&db, &settings, ids[0].0, weight, item_num).await;
+        }
+        _ => {
+            return err("该订单有多个包裹, 请按包裹更新".to_string());
+        }
     }
-    let order = order_.unwrap();
-    if order.weight > 0 {
+}
+
+pub async fn update_shipment_weight(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Path((id, weight, item_num)): Path<(i64, i64, i64)>,
+) -> Result<Res, AeError> {
+    return update_shipment(&db, &settings, id, weight, item_num).await;
+}
+
+//记录包裹重量, 包裹内只有一种商品时用于计算该商品的重量
+async fn update_shipment(
+    db: &SqlitePool,
+    settings: &Settings,
+    id: i64,
+    weight: i64,
+    item_num: i64,
+) -> Result<Res, AeError> {
+    let shipment_: Option<Shipment> = query_as("select * from shipments where id=?")
+        .bind(id)
+        .fetch_optional(db)
+        .await?;
+    if shipment_.is_none() {
+        return err("未找到该包裹".to_string());
+    }
+    let shipment = shipment_.unwrap();
+    if shipment.weight > 0 {
         return err("已统计".to_string());
     }
-    let affacted_rows = query("update orders set weight=?,updated_at=? where order_id=?")
+    let now = OffsetDateTime::now_local()?;
+    let affacted_rows = query("update shipments set weight=?,updated_at=? where id=? and weight=0")
         .bind(weight)
-        .bind(OffsetDateTime::now_local()?)
-        .bind(oid)
-        .execute(&db)
+        .bind(now)
+        .bind(id)
+        .execute(db)
         .await?
         .rows_affected();
     if affacted_rows == 0 {
-        return err("未能更新该订单".to_string());
+        return err("未能更新该包裹".to_string());
     }
+    //订单重量为各包裹重量之和
+    query("update orders set weight=(select coalesce(sum(weight),0) from shipments where order_id=?1),updated_at=?2 where order_id=?1")
+        .bind(shipment.order_id)
+        .bind(now)
+        .execute(db)
+        .await?;
+    leases::release(db, leases::SHIPMENT, id).await?;
 
-    if order.product_num != 1 || item_num != order.item_num {
-        return ok(json!("多商品或分包订单无法统计重量"));
+    let items = match shipments::parcel_items(&mut *db.acquire().await?, &shipment).await? {
+        Some(items) => items,
+        None => {
+            return ok(json!("未记录包裹内的商品, 无法统计重量"));
+        }
+    };
+    let pids: HashSet<i64> = items.iter().map(|i| i.0).collect();
+    let qty: i64 = items.iter().map(|i| i.1).sum();
+    if pids.len() != 1 || qty != item_num {
+        return ok(json!("多商品或件数不符的包裹无法统计重量"));
     }
 
     if weight < 10 {
         return ok(json!("重量过低, 非正常包裹"));
     }
 
-    let one_product_id = if let Some(pid) =
-        from_str::<HashMap<i64, Vec<(String, i64, i64)>>>(&order.products)?
-            .keys()
-            .next()
-    {
-        pid.clone()
-    } else {
-        return err("未找到对应的product".to_string());
-    };
-
+    let one_product_id = items[0].0;
     let product_: Option<Product> = query_as("SELECT * FROM products WHERE product_id = ?1")
         .bind(one_product_id)
-        .fetch_optional(&db)
+        .fetch_optional(db)
         .await?;
     if product_.is_none() {
-        return err("未能获取该订单的产品".to_string());
+        return err("未能获取该包裹的产品".to_string());
     }
     let mut pd = product_.unwrap();
 
     let orig_weight_cal_count = pd.weight_cal_count;
 
-    pd.weight_cal_count += order.item_num;
+    pd.weight_cal_count += qty;
     pd.sale_weight += weight;
-
-    let weight_ratio = if let Some(wr) = settings["WEIGHT_RATIO"].as_i64() {
-        wr
-    } else {
-        return Err(anyhow!("no weight_ratio").into());
-    };
+    let weight_ratio = settings.weight_ratio;
     pd.weight = (pd.sale_weight / (pd.weight_cal_count)) * 1000 / weight_ratio;
 
-    let need_update_weight = if let Some(nuw) = settings["NEED_UPDATE_WEIGHT"].as_i64() {
-        nuw
-    } else {
-        return Err(anyhow!("no need_update_weight").into());
-    };
+    let need_update_weight = settings.need_update_weight;
 
-    if pd.weight_cal_count == 1
+    //第一次统计的包裹可能不止1件
+    if orig_weight_cal_count == 0
         || pd.weight_cal_count < 33
             && (pd.weight_cal_count as f64).log2() as i64
                 - (orig_weight_cal_count as f64).log2() as i64
@@ -338,11 +467,11 @@ pub async fn update_weight(
                     .bind(pd.weight)
                     .bind(pd.pending)
                     .bind(pd.product_id)
-                    .execute(&db)
+                    .execute(db)
                     .await?
                     .rows_affected();
     if affacted_rows == 0 {
-        return err("未能更新该订单所含产品".to_string());
+        return err("未能更新该包裹所含产品".to_string());
     } else {
         return ok(json!({}));
     }
@@ -354,6 +483,9 @@ pub struct SOReq {
     per_page: i64,
     order_id: i64,
     product_id: i64,
+    //为1时只显示需要关注的订单
+    #[serde(default)]
+    attention: i64,
 }
 pub async fn admin_order_show(
     State(AEState {
@@ -372,11 +504,19 @@ pub async fn admin_order_show(
         orders_query_builder.push_bind(search.order_id);
     }
     if search.product_id > 0 {
-        let like_str = format!("%{}%", search.product_id);
-        total_query_builder.push(" and products like ");
-        total_query_builder.push_bind(like_str.clone());
-        orders_query_builder.push(" and products like ");
-        orders_query_builder.push_bind(like_str.clone());
+        total_query_builder
+            .push(" and order_id in (select order_id from order_items where product_id = ");
+        total_query_builder.push_bind(search.product_id);
+        total_query_builder.push(")");
+        orders_query_builder
+            .push(" and order_id in (select order_id from order_items where product_id = ");
+        orders_query_builder.push_bind(search.product_id);
+        orders_query_builder.push(")");
+    }
+
+    if search.attention == 1 {
+        total_query_builder.push(" and attention = 1");
+        orders_query_builder.push(" and attention = 1");
     }
 
     let total: (i64,) = total_query_builder.build_query_as().fetch_one(&db).await?;
@@ -401,3 +541,152 @@ pub async fn admin_order_show(
         "orders": orders,
     }));
 }
+
+//订单的所有包裹
+pub async fn admin_order_shipments(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(order_id): Path<i64>,
+) -> Result<Res, AeError> {
+    return ok(json!(
+        shipments::of_order(&mut *db.acquire().await?, order_id).await?
+    ));
+}
+
+//订单的备注记录, 按时间排序
+pub async fn admin_order_notes(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(order_id): Path<i64>,
+) -> Result<Res, AeError> {
+    return ok(json!(order_notes::of_order(&db, order_id).await?));
+}
+
+#[derive(Deserialize)]
+pub struct AddNote {
+    order_id: i64,
+    #[serde(default)]
+    author: String,
+    content: String,
+    //同时设置是否需要关注, 不传则不变
+    attention: Option<i64>,
+}
+//后台添加订单备注
+pub async fn admin_order_add_note(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Json(note): Json<AddNote>,
+) -> Result<Res, AeError> {
+    let content = note.content.trim();
+    if content.is_empty() {
+        return err("备注内容不能为空".to_string());
+    }
+    let mut db_trans = db.begin().await?;
+    let exists: Option<(i64,)> = query_as("select id from orders where order_id=?")
+        .bind(note.order_id)
+        .fetch_optional(&mut *db_trans)
+        .await?;
+    if exists.is_none() {
+        return err("没有该订单".to_string());
+    }
+    let id = order_notes::add(
+        &mut db_trans,
+        note.order_id,
+        order_notes::ADMIN,
+        note.author.trim(),
+        content,
+    )
+    .await?;
+    if let Some(attention) = note.attention {
+        query("update orders set attention=? where order_id=?")
+            .bind((attention == 1) as i64)
+            .bind(note.order_id)
+            .execute(&mut *db_trans)
+            .await?;
+    }
+    db_trans.commit().await?;
+    return ok(json!(id));
+}
+
+//设置订单是否需要关注
+pub async fn admin_order_attention(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path((order_id, tf)): Path<(i64, i64)>,
+) -> Result<Res, AeError> {
+    let affacted_rows = query("update orders set attention=? where order_id=? and attention!=?")
+        .bind((tf == 1) as i64)
+        .bind(order_id)
+        .bind((tf == 1) as i64)
+        .execute(&db)
+        .await?
+        .rows_affected();
+    if affacted_rows > 0 {
+        return ok(json!(()));
+    } else {
+        return ok(json!("未改变任何数据"));
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PickReq {
+    #[serde(default)]
+    order_ids: Vec<i64>,
+    //下单日期 "YYYY-MM-DD"或"YYYY-MM-DD~YYYY-MM-DD", 没有order_ids时使用, 都没有为今天
+    #[serde(default)]
+    date: String,
+    //xlsx 或 html(可直接打印)
+    #[serde(default)]
+    format: String,
+}
+//生成拣货单的后台任务, 完成后通过 /admin/jobs/file/:id 下载
+pub async fn admin_order_picking(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Json(req): Json<PickReq>,
+) -> Result<Res, AeError> {
+    let format = match &req.format[..] {
+        "" | "xlsx" => "xlsx",
+        "html" => "html",
+        _ => {
+            return err("format只能是xlsx或html".to_string());
+        }
+    };
+    let range = if req.date.trim().is_empty() {
+        None
+    } else {
+        Some(analytics::parse_date_range(&req.date)?)
+    };
+    let norm = Normalizer::from_settings(&settings);
+    let tmp_dir = settings.tmp_dir.clone();
+    let job_db = db.clone();
+    let id = jobs::start(
+        &db,
+        jobs::PICKING_LIST,
+        json!({ "order_ids": req.order_ids, "date": req.date, "format": format }),
+        move |job| async move {
+            picking::export(
+                &job_db,
+                &norm,
+                &tmp_dir,
+                &req.order_ids,
+                range,
+                format,
+                &job,
+            )
+            .await
+        },
+    )
+    .await?;
+    return ok(json!(id));
+}
@@ -1,32 +1,32 @@
+use crate::analytics::{self, ColumnTitles, ImportReport, ParsedUpload, ProductChange};
+use crate::config::Settings;
+use crate::jobs::{self, JobCtx};
 use crate::models::{NewProduct, Offer, Product};
+use crate::normalize::Normalizer;
 use crate::types::{err, ok, AEState, AeError, Res};
+use crate::{links, maintenance, scheduler};
 use axum::{
-    body::Body,
+    body::Bytes,
     extract::Multipart,
-    extract::{Json, Path, State},
-    http::StatusCode,
-    response::Response,
+    extract::{Json, Path, Query, State},
 };
-use calamine::{open_workbook, Data, DataType, Reader, Xlsx};
-use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, json, Value};
-use sqlx::{query, query_as, FromRow, QueryBuilder, Row};
+use serde_json::{json, Value};
+use sqlx::{query, query_as, FromRow, QueryBuilder, Row, SqlitePool};
 use std::{
     cmp::{max, min},
     collections::HashMap,
-    fs,
     path::PathBuf,
 };
-use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+use time::OffsetDateTime;
 use tracing::{debug, error};
 
 pub async fn new(
     State(AEState {
         db_pool: db,
-        settings: _,
+        settings,
     }): State<AEState>,
-    Json(np): Json<NewProduct>,
+    Json(mut np): Json<NewProduct>,
 ) -> Result<Res, AeError> {
     if let Some(_) = query("SELECT id FROM products WHERE product_id = ?1")
         .bind(np.product_id)
@@ -36,6 +36,7 @@ pub async fn new(
         return err("该product_id已存在".to_string());
     }
 
+    np.stock_info = Normalizer::from_settings(&settings).sku_map_str(&np.stock_info)?;
     let product = Product::new(&np);
 
     let id = query("INSERT INTO products (uv30,sales30,sale_record,offer_id,discount,stock_count,sale_count,sale_info,sale_weight,weight_cal_count,weight,inited_weight,pending,tips,created_at,updated_at,deleted_at,product_id,title,cover,price,stock_info,model_id) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20,?21,?22,?23)")
@@ -88,10 +89,11 @@ pub async fn get(
 pub async fn update(
     State(AEState {
         db_pool: db,
-        settings: _,
+        settings,
     }): State<AEState>,
-    Json(np): Json<NewProduct>,
+    Json(mut np): Json<NewProduct>,
 ) -> Result<Res, AeError> {
+    np.stock_info = Normalizer::from_settings(&settings).sku_map_str(&np.stock_info)?;
     let product_: Option<Product> = query_as("SELECT * FROM products WHERE product_id = ?1")
         .bind(np.product_id)
         .fetch_optional(&db)
@@ -140,10 +142,11 @@ pub async fn products_from_ids(
         del: &'static str,
         sale30: i64,
         sku_props_colors: String,
+        primary: bool,
     }
     let mut ofs: HashMap<i64, Vec<Of>> = HashMap::new();
     let query_str = format!(
-        "SELECT * FROM offers WHERE product_id in ({})",
+        "SELECT offers.*, l.product_id AS link_product_id, l.is_primary FROM offer_products l JOIN offers ON offers.offer_id = l.offer_id WHERE l.product_id in ({}) ORDER BY l.is_primary DESC, l.id ASC",
         vec!["?"; product_ids.len()].join(",")
     );
     let mut query_ = query(&query_str);
@@ -156,6 +159,9 @@ pub async fn products_from_ids(
         .into_iter()
         .map(|row| match Offer::from_row(&row) {
             Ok(offer) => {
+                //一个offer可能供多个product, 按关联表的product_id分组
+                let product_id: i64 = row.get("link_product_id");
+                let primary: i64 = row.get("is_primary");
                 let of = Of {
                     offer_id: offer.offer_id,
                     better_price: offer.better_price,
@@ -170,12 +176,9 @@ pub async fn products_from_ids(
                     sku_props_colors: (serde_json::from_str::<Value>(&offer.sku_info_use).unwrap())
                         ["skuProps"][0]["value"]
                         .to_string(),
+                    primary: primary == 1,
                 };
-                if ofs.contains_key(&offer.product_id) {
-                    ofs.get_mut(&offer.product_id).unwrap().push(of);
-                } else {
-                    ofs.insert(offer.product_id, vec![of]);
-                }
+                ofs.entry(product_id).or_default().push(of);
             }
             Err(e) => {
                 error!("parse offer error: {:?}", e);
@@ -223,7 +226,7 @@ pub struct UseStock {
 pub async fn ship_use_stock(
     State(AEState {
         db_pool: db,
-        settings: _,
+        settings,
     }): State<AEState>,
     Json(mut want): Json<UseStock>,
 ) -> Result<Res, AeError> {
@@ -232,11 +235,12 @@ pub async fn ship_use_stock(
         .fetch_optional(&db)
         .await?;
     if let Some(product) = product_ {
-        //避免大小写造成的不匹配
-        want.sku[0] = want.sku[0].to_uppercase();
-        want.sku[1] = want.sku[1].to_uppercase();
+        //避免大小写和别名造成的不匹配
+        let norm = Normalizer::from_settings(&settings);
+        want.sku[0] = norm.color(&want.sku[0]);
+        want.sku[1] = norm.size(&want.sku[1]);
 
-        let mut stock_info: Value = serde_json::from_str(&product.stock_info.to_uppercase())?;
+        let mut stock_info = norm.sku_map(&serde_json::from_str(&product.stock_info)?);
         if let Some(quantity_in_stock) = stock_info[&want.sku[0]][&want.sku[1]].as_i64() {
             if quantity_in_stock >= want.quantity {
                 stock_info[&want.sku[0]][&want.sku[1]] = json!(quantity_in_stock - want.quantity);
@@ -297,6 +301,8 @@ pub struct SOReq {
     inited_weight: i64,
     pending: i64,
     deleted: bool,
+    #[serde(default)]
+    offer_unavailable: bool,
 }
 pub async fn admin_product_show(
     State(AEState {
@@ -332,6 +338,10 @@ pub async fn admin_product_show(
         products_query_builder.push(" and pending = ");
         products_query_builder.push_bind(search.pending);
     }
+    if search.offer_unavailable {
+        total_query_builder.push(" and offer_unavailable = 1");
+        products_query_builder.push(" and offer_unavailable = 1");
+    }
     if search.deleted {
         total_query_builder.push(" and deleted_at is not null");
         products_query_builder.push(" and deleted_at is not null");
@@ -463,16 +473,22 @@ pub async fn admin_product_tips(
     }
 }
 
-pub async fn admin_product_oid(
+#[derive(Deserialize)]
+pub struct PLReq {
+    id: i64,
+    location: String,
+}
+//库存存放位置, 用于拣货单
+pub async fn admin_product_location(
     State(AEState {
         db_pool: db,
         settings: _,
     }): State<AEState>,
-    Path((id, oid)): Path<(i64, i64)>,
+    Json(req): Json<PLReq>,
 ) -> Result<Res, AeError> {
-    if query("update products set offer_id=? where id=?")
-        .bind(oid)
-        .bind(id)
+    if query("update products set stock_location=? where id=?")
+        .bind(req.location.trim())
+        .bind(req.id)
         .execute(&db)
         .await?
         .rows_affected()
@@ -484,6 +500,38 @@ pub async fn admin_product_oid(
     }
 }
 
+pub async fn admin_product_oid(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path((id, oid)): Path<(i64, i64)>,
+) -> Result<Res, AeError> {
+    let product_: Option<(i64,)> = query_as("select product_id from products where id=?")
+        .bind(id)
+        .fetch_optional(&db)
+        .await?;
+    let product_id = match product_ {
+        Some((product_id,)) => product_id,
+        None => {
+            return err("not found".to_string());
+        }
+    };
+    //设置主offer, 原主offer保留为备用; oid为0时取消主offer
+    let mut db_trans = db.begin().await?;
+    let changed = if oid == 0 {
+        links::set_primary(&mut db_trans, product_id, 0).await?
+    } else {
+        links::link(&mut db_trans, oid, product_id, true).await?
+    };
+    db_trans.commit().await?;
+    if changed {
+        return ok(json!(()));
+    } else {
+        return ok(json!("未改变任何数据"));
+    }
+}
+
 pub async fn admin_product_clear_stock_info(
     State(AEState {
         db_pool: db,
@@ -513,9 +561,9 @@ pub struct UpInfo {
 pub async fn admin_product_update_info(
     State(AEState {
         db_pool: db,
-        settings: _,
+        settings,
     }): State<AEState>,
-    Json(req): Json<UpInfo>,
+    Json(mut req): Json<UpInfo>,
 ) -> Result<Res, AeError> {
     let (column_info, column_count) = match &req.column[..] {
         "sale_info" => ("sale_info", "sale_count"),
@@ -524,6 +572,7 @@ pub async fn admin_product_update_info(
             return err("错误的更新请求字段".to_string());
         }
     };
+    req.info = Normalizer::from_settings(&settings).sku_map_str(&req.info)?;
     let mut count: i64 = 0; //总量
     let info: HashMap<String, HashMap<String, i64>> = serde_json::from_str(&req.info)?;
     for m in info.values() {
@@ -571,13 +620,32 @@ pub async fn admin_product_discount(
     }
 }
 
+//生成折扣导入表的后台任务, 完成后通过 /admin/jobs/file/:id 下载
 pub async fn admin_product_dl_discount_xslx(
     State(AEState {
         db_pool: db,
         settings,
     }): State<AEState>,
     Path(default_discount): Path<i64>,
-) -> Result<Response, AeError> {
+) -> Result<Res, AeError> {
+    let tmp_dir = settings.tmp_dir.clone();
+    let job_db = db.clone();
+    let id = jobs::start(
+        &db,
+        jobs::DISCOUNT_XLSX,
+        json!({ "default_discount": default_discount }),
+        move |job| async move { discount_xlsx(&job_db, &tmp_dir, default_discount, &job).await },
+    )
+    .await?;
+    return ok(json!(id));
+}
+
+async fn discount_xlsx(
+    db: &SqlitePool,
+    tmp_dir: &str,
+    default_discount: i64,
+    job: &JobCtx,
+) -> anyhow::Result<Value> {
     use rust_xlsxwriter::{Format, Workbook};
     let mut workbook = Workbook::new();
 
@@ -597,214 +665,185 @@ pub async fn admin_product_dl_discount_xslx(
 
     //数字格式
     let decimal_format = Format::new().set_num_format("###0");
-    query("select p.id,p.product_id,p.discount as adjust,o.discount as base from products p left join offers o on p.offer_id=o.offer_id where p.deleted_at is null AND o.deleted_at is null").fetch_all(&db)
-    .await?
-    .iter()
-    .enumerate()
-    .for_each(|(i,row)| {
-        let product_id:i64 = row.get("product_id");
-        let base:i64 = row.get("base");
-        let adjust:i64 = row.get("adjust");
-        let discount:i64 = 100 - (100 - base) * (100 - adjust) * (100 - default_discount) / 10000;
-        let id:i64 = row.get("id");
+    let rows = query("select p.id,p.product_id,p.discount as adjust,o.discount as base from products p left join offers o on p.offer_id=o.offer_id where p.deleted_at is null AND o.deleted_at is null").fetch_all(db)
+    .await?;
+    rows.iter().enumerate().for_each(|(i, row)| {
+        let product_id: i64 = row.get("product_id");
+        let base: i64 = row.get("base");
+        let adjust: i64 = row.get("adjust");
+        let discount: i64 = 100 - (100 - base) * (100 - adjust) * (100 - default_discount) / 10000;
+        let id: i64 = row.get("id");
         //row
-        worksheet.write(i as u32 + 1, 0, product_id.to_string()).unwrap();
+        worksheet
+            .write(i as u32 + 1, 0, product_id.to_string())
+            .unwrap();
         worksheet.write(i as u32 + 1, 1, "").unwrap();
-        worksheet.write_with_format(i as u32 + 1, 2, discount, &decimal_format).unwrap();
+        worksheet
+            .write_with_format(i as u32 + 1, 2, discount, &decimal_format)
+            .unwrap();
         worksheet.write(i as u32 + 1, 3, "").unwrap();
         worksheet.write(i as u32 + 1, 4, "").unwrap();
         worksheet.write(i as u32 + 1, 5, "").unwrap();
-        worksheet.write_with_format(i as u32 + 1, 6, id, &decimal_format).unwrap();
+        worksheet
+            .write_with_format(i as u32 + 1, 6, id, &decimal_format)
+            .unwrap();
     });
+    job.progress(rows.len() as i64, rows.len() as i64).await?;
 
-    let xlsx_name = &format!("product_discount-{}.xlsx", default_discount);
-    let tmp_dir = settings["TMP_DIR"].as_str().unwrap_or("tmp");
+    //文件名带上任务id, 避免同时导出时互相覆盖
+    let xlsx_name = &format!("product_discount-{}-{}.xlsx", default_discount, job.id);
     let file_path = PathBuf::from(tmp_dir).join(xlsx_name);
 
     workbook.save(&file_path)?; //最后保存文件
 
     debug!("created discount file, path: {:?}", &file_path);
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(
-            "Content-Disposition",
-            format!("attachment; filename=\"{}\"", xlsx_name),
-        )
-        .header(
-            "Content-Type",
-            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-        )
-        .body(Body::from(fs::read(&file_path)?))?)
+    Ok(jobs::file_result(xlsx_name))
 }
 
+#[derive(Deserialize)]
+pub struct UploadOpt {
+    #[serde(default)]
+    dry_run: bool, //只计算变更, 返回预览token, 不写入
+}
+//文件在请求中解析, 计算和写入在后台任务中进行, 返回任务id
 pub async fn admin_product_upload_xlsx(
     State(AEState {
         db_pool: db,
         settings,
     }): State<AEState>,
+    Query(opt): Query<UploadOpt>,
     mut multipart: Multipart,
 ) -> Result<Res, AeError> {
-    let pid_title = settings["XLSX_PID_COLUMN_TITLE"]
-        .as_str()
-        .unwrap_or("|商品ID|");
-    let uv30_title = settings["XLSX_UV30_COLUMN_TITLE"]
-        .as_str()
-        .unwrap_or("|访客数|");
-    let sales30_title = settings["XLSX_SALES30_COLUMN_TITLE"]
-        .as_str()
-        .unwrap_or("|支付商品件数|");
-    let barrier_uv30 = settings["UNPUBLISH_BARRIER_UV30"].as_i64().unwrap_or(10);
-    let now = OffsetDateTime::now_local()?;
-    let today = now.date();
-    let days_before = now - Duration::days(settings["ANALYSIS_BEFORE"].as_i64().unwrap_or(180));
-    let sql_str = "update products set uv30=?,sales30=?,sale_record=?,updated_at=? where id=?";
-    let sql_str_2 =
-        "update products set uv30=?,sales30=?,sale_record=?,updated_at=?,pending=-2 where id=?";
-
-    let reg = Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap();
+    let titles = ColumnTitles::from_settings(&settings);
 
+    //表单字段顺序不固定, 先收集日期和文件
+    let mut form_date: Option<String> = None;
+    let mut files: Vec<(String, Bytes)> = vec![];
     while let Some(field) = multipart.next_field().await? {
-        let file_name = field.file_name().unwrap().to_string();
-        let date = match reg.find(&file_name) {
-            Some(m) => m.as_str(),
-            None => {
-                return err("file name not contain date".to_string());
-            }
-        };
-        let data = field.bytes().await?;
-        let file_path =
-            PathBuf::from(settings["TMP_DIR"].as_str().unwrap_or("tmp")).join(&file_name);
-        fs::write(&file_path, data)?;
-
-        let mut workbook: Xlsx<_> = open_workbook(&file_path)?;
-        let sheets = workbook.sheet_names().to_owned();
-
-        if let Ok(sheet_rows) = workbook.worksheet_range(&sheets[0]) {
-            let mut pid_i = 0; //记录pid所在列
-            let mut uv30_i = 0; //记录uv30所在列
-            let mut sales30_i = 0; //记录sales30所在列
-            let mut records: HashMap<i64, (String, i64, i64)> = HashMap::new();
-
-            for (i, row) in sheet_rows.rows().enumerate() {
-                if i == 0 {
-                    for (j, title) in row.iter().enumerate() {
-                        match title {
-                            Data::String(s) => {
-                                let s = "|".to_string() + s + "|";
-                                if pid_title.contains(&s) {
-                                    //第j列是pid
-                                    pid_i = j;
-                                } else if uv30_title.contains(&s) {
-                                    //第j列是uv30
-                                    uv30_i = j;
-                                } else if sales30_title.contains(&s) {
-                                    //第j列是sales30
-                                    sales30_i = j;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                } else {
-                    let pid = row[pid_i]
-                        .get_string()
-                        .unwrap_or("0")
-                        .parse::<i64>()
-                        .unwrap_or(0);
-                    let uv = row[uv30_i]
-                        .get_string()
-                        .unwrap_or("0")
-                        .parse::<i64>()
-                        .unwrap_or(0);
-                    let sale = row[sales30_i]
-                        .get_string()
-                        .unwrap_or("0")
-                        .parse::<i64>()
-                        .unwrap_or(0);
-
-                    records.insert(pid, (date.to_string(), uv, sale));
-                }
+        if let Some(file_name) = field.file_name() {
+            let file_name = file_name.to_string();
+            files.push((file_name, field.bytes().await?));
+        } else if field.name() == Some("date") {
+            let text = field.text().await?;
+            if !text.trim().is_empty() {
+                form_date = Some(text);
             }
+        }
+    }
+    if files.is_empty() {
+        return err("没有上传文件".to_string());
+    }
+    let form_date = match form_date {
+        Some(d) => Some(analytics::parse_date_range(&d)?),
+        None => None,
+    };
 
-            let mut current_id = 0;
-            let max_id: (i64,) = query_as(
-                "select id from products where deleted_at is null order by id desc limit 1",
-            )
-            .fetch_one(&db)
-            .await?;
-            let max_id = max_id.0;
-            while current_id < max_id {
-                let rows = query("select id,product_id,sale_record,created_at from products where id>? and deleted_at is null order by id asc limit 50").bind(current_id).fetch_all(&db).await?;
-                for row in rows {
-                    let id: i64 = row.get("id");
-                    current_id = max(current_id, id);
-                    let product_id: i64 = row.get("product_id");
-                    let mut sale_record_str: String = row.get("sale_record");
-                    let created_at = OffsetDateTime::parse(row.get("created_at"), &Rfc3339)?;
-                    let sale_record_this_day: Value =
-                        if let Some((date, uv, sale)) = records.get(&product_id) {
-                            json!({
-                                "date": date,
-                                "sale": sale,
-                                "uv": uv
-                            })
-                        } else {
-                            json!({
-                                "date": date,
-                                "sale": 0,
-                                "uv": 0
-                            })
-                        };
-                    let mut sale_record = from_str::<Value>(&sale_record_str)
-                        .unwrap()
-                        .as_array()
-                        .unwrap()
-                        .to_owned();
-                    if sale_record.len() == 0 || sale_record[0]["date"].as_str().unwrap() != date {
-                        sale_record.insert(0, sale_record_this_day);
-                        while sale_record.len() > 400 {
-                            sale_record.pop();
-                        }
-                    }
-                    sale_record_str = json!(sale_record).to_string();
-                    let (uv30, sales30) = match sale_record.chunks(30).next() {
-                        Some(recent30) => {
-                            let mut u = 0;
-                            let mut s = 0;
-                            recent30.iter().for_each(|v| {
-                                u += v["uv"].as_i64().unwrap_or(0);
-                                s += v["sale"].as_i64().unwrap_or(0);
-                            });
-                            (u, s)
-                        }
-                        None => (0, 0),
-                    };
-
-                    if created_at < days_before && uv30 < barrier_uv30 {
-                        query(sql_str_2)
-                    } else {
-                        query(sql_str)
-                    }
-                    .bind(uv30)
-                    .bind(sales30)
-                    .bind(sale_record_str)
-                    .bind(now)
-                    .bind(id)
-                    .execute(&db)
-                    .await?;
-                }
+    let mut uploads = vec![];
+    for (file_name, data) in files {
+        //表中没有日期列时, 使用表单或文件名中的日期
+        let range = form_date.or_else(|| analytics::date_range_from_file_name(&file_name));
+        match analytics::parse_upload(&file_name, data.to_vec(), range, &titles) {
+            Ok(upload) => uploads.push(upload),
+            Err(e) => {
+                return err(format!("{}: {}", file_name, e));
             }
         }
     }
 
-    //删除180天前废弃的products
-    query("delete from products where deleted_at is not null and deleted_at < ?")
-        .bind(today - Duration::days(180))
-        .execute(&db)
-        .await?;
+    let params = json!({
+        "files": uploads.iter().map(|u| &u.report.file).collect::<Vec<&String>>(),
+        "dry_run": opt.dry_run,
+    });
+    let job_db = db.clone();
+    let id = jobs::start(&db, jobs::UPLOAD_XLSX, params, move |job| async move {
+        upload_xlsx(&job_db, &settings, uploads, opt.dry_run, &job).await
+    })
+    .await?;
+    return ok(json!(id));
+}
 
-    return ok(json!(()));
+async fn upload_xlsx(
+    db: &SqlitePool,
+    settings: &Settings,
+    mut uploads: Vec<ParsedUpload>,
+    dry_run: bool,
+    job: &JobCtx,
+) -> anyhow::Result<Value> {
+    let changes = analytics::compute_changes(db, settings, &mut uploads, job).await?;
+    let reports: Vec<ImportReport> = uploads.into_iter().map(|u| u.report).collect();
+
+    if dry_run {
+        let tmp_dir = settings.tmp_dir.as_str();
+        let preview = analytics::save_preview(tmp_dir, reports, changes)?;
+        let changed: Vec<&ProductChange> = preview.changes.iter().map(|c| &c.change).collect();
+        return Ok(json!({
+            "token": preview.token,
+            "reports": preview.reports,
+            "changes": changed,
+            "delisted": changed.iter().filter(|c| c.delist).map(|c| c.product_id).collect::<Vec<i64>>(),
+        }));
+    }
+
+    let (updated, conflicts) = analytics::apply_changes(db, &changes, job).await?;
+
+    Ok(json!({
+        "reports": reports,
+        "updated": updated,
+        "conflicts": conflicts,
+        "delisted": changes.iter().filter(|c| c.change.delist).map(|c| c.change.product_id).collect::<Vec<i64>>(),
+    }))
+}
+
+//提交预览, 预览计算后sale_record有变化的product不会更新, 在conflicts中返回
+pub async fn admin_product_upload_commit(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Path(token): Path<String>,
+) -> Result<Res, AeError> {
+    let preview = match analytics::take_preview(settings.tmp_dir.as_str(), &token) {
+        Ok(preview) => preview,
+        Err(e) => {
+            return err(e.to_string());
+        }
+    };
+    let job_db = db.clone();
+    let id = jobs::start(
+        &db,
+        jobs::UPLOAD_COMMIT,
+        json!({ "token": token }),
+        move |job| async move {
+            let (updated, conflicts) =
+                analytics::apply_changes(&job_db, &preview.changes, &job).await?;
+            Ok(json!({
+                "reports": preview.reports,
+                "updated": updated,
+                "conflicts": conflicts,
+                "delisted": preview.changes.iter().filter(|c| c.change.delist).map(|c| c.change.product_id).collect::<Vec<i64>>(),
+            }))
+        },
+    )
+    .await?;
+    return ok(json!(id));
+}
+
+//没有新数据时按当前日期重新计算uv30/sales30, 并检查是否需要下架
+pub async fn admin_product_recompute_traffic(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+) -> Result<Res, AeError> {
+    match scheduler::run_task(&db, &settings, maintenance::RECOMPUTE_TRAFFIC).await {
+        Ok(id) => {
+            return ok(json!(id));
+        }
+        Err(e) => {
+            return err(e.to_string());
+        }
+    }
 }
 
 pub async fn admin_product_available(
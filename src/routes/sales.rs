@@ -0,0 +1,54 @@
+use crate::analytics;
+use crate::normalize::Normalizer;
+use crate::sales;
+use crate::types::{ok, AEState, AeError, Res};
+use axum::extract::{Query, State};
+use serde::Deserialize;
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+
+#[derive(Deserialize)]
+pub struct StatsReq {
+    //day, week 或 month, 默认day
+    period: Option<String>,
+    product_id: Option<i64>,
+    //下单日期 "YYYY-MM-DD"或"YYYY-MM-DD~YYYY-MM-DD", 默认最近30天
+    date: Option<String>,
+    //为1时按sku分别统计
+    #[serde(default)]
+    by_sku: i64,
+}
+//按订单统计的销量和销售额
+pub async fn admin_sales_stats(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Query(req): Query<StatsReq>,
+) -> Result<Res, AeError> {
+    let range = match req.date.as_deref().map(|d| d.trim()) {
+        Some(date) if !date.is_empty() => analytics::parse_date_range(date)?,
+        _ => {
+            let today = OffsetDateTime::now_local()?.date();
+            (today - Duration::days(29), today)
+        }
+    };
+    let period = req.period.unwrap_or(sales::DAY.to_string());
+    let stats = sales::stats(
+        &db,
+        &Normalizer::from_settings(&settings),
+        &period,
+        req.product_id,
+        range,
+        req.by_sku == 1,
+    )
+    .await?;
+    return ok(json!({
+        "period": period,
+        "from": range.0.to_string(),
+        "to": range.1.to_string(),
+        "units": stats.iter().map(|s| s.units).sum::<i64>(),
+        "revenue": stats.iter().map(|s| s.revenue).sum::<i64>(),
+        "stats": stats,
+    }));
+}
@@ -0,0 +1,143 @@
+use crate::models::Offer;
+use crate::normalize::Normalizer;
+use crate::skus;
+use crate::types::{err, ok, AEState, AeError, Res};
+use axum::extract::{Json, Path, Query, State};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{query, query_as};
+use time::OffsetDateTime;
+
+#[derive(Deserialize)]
+pub struct SkuListReq {
+    //默认为主offer
+    offer_id: Option<i64>,
+}
+//product的sku对应关系, 以及offer的颜色尺码供手动选择
+pub async fn admin_sku_list(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Path(product_id): Path<i64>,
+    Query(req): Query<SkuListReq>,
+) -> Result<Res, AeError> {
+    let offer_id = match req.offer_id {
+        Some(offer_id) => offer_id,
+        None => {
+            let primary: Option<(i64,)> =
+                query_as("select offer_id from products where product_id=?")
+                    .bind(product_id)
+                    .fetch_optional(&db)
+                    .await?;
+            match primary {
+                Some((offer_id,)) => offer_id,
+                None => {
+                    return err("没有该product".to_string());
+                }
+            }
+        }
+    };
+    if offer_id == 0 {
+        return err("该product没有主offer".to_string());
+    }
+    let offer: Option<Offer> = query_as("select * from offers where offer_id=?")
+        .bind(offer_id)
+        .fetch_optional(&db)
+        .await?;
+    let (colors, sizes) = match offer {
+        Some(offer) => skus::sku_props(&offer),
+        None => {
+            return err("没有该offer".to_string());
+        }
+    };
+    let mappings = skus::mappings(
+        &db,
+        &Normalizer::from_settings(&settings),
+        product_id,
+        offer_id,
+    )
+    .await?;
+    return ok(json!({
+        "offer_id": offer_id,
+        "mappings": mappings,
+        "colors": colors,
+        "sizes": sizes,
+    }));
+}
+
+#[derive(Deserialize)]
+pub struct SetSku {
+    product_id: i64,
+    offer_id: i64,
+    ae_color: String,
+    ae_size: String,
+    offer_color: String,
+    offer_size: String,
+    sku_id: String,
+}
+//手动设置, 之后重新推荐不会覆盖
+pub async fn admin_sku_set(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Json(set): Json<SetSku>,
+) -> Result<Res, AeError> {
+    if set.product_id <= 0 || set.offer_id <= 0 {
+        return err("product_id和offer_id不能为0".to_string());
+    }
+    let norm = Normalizer::from_settings(&settings);
+    let now = OffsetDateTime::now_local()?;
+    query("insert into sku_mappings (product_id,ae_color,ae_size,offer_id,offer_color,offer_size,sku_id,manual,created_at,updated_at) values (?,?,?,?,?,?,?,1,?,?) on conflict(product_id,offer_id,ae_color,ae_size) do update set offer_color=excluded.offer_color,offer_size=excluded.offer_size,sku_id=excluded.sku_id,manual=1,updated_at=excluded.updated_at")
+        .bind(set.product_id)
+        .bind(norm.color(&set.ae_color))
+        .bind(norm.size(&set.ae_size))
+        .bind(set.offer_id)
+        .bind(set.offer_color)
+        .bind(set.offer_size)
+        .bind(set.sku_id)
+        .bind(now)
+        .bind(now)
+        .execute(&db)
+        .await?;
+    return ok(json!(()));
+}
+
+//按颜色序号和尺码重新推荐, 手动设置过的不变
+pub async fn admin_sku_suggest(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Path((product_id, offer_id)): Path<(i64, i64)>,
+) -> Result<Res, AeError> {
+    let changed = skus::suggest_for(
+        &db,
+        &Normalizer::from_settings(&settings),
+        product_id,
+        offer_id,
+    )
+    .await?;
+    return ok(json!(changed));
+}
+
+//删除后下次使用时重新推荐
+pub async fn admin_sku_delete(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(id): Path<i64>,
+) -> Result<Res, AeError> {
+    let deleted = query("delete from sku_mappings where id=?")
+        .bind(id)
+        .execute(&db)
+        .await?
+        .rows_affected();
+    if deleted > 0 {
+        return ok(json!(()));
+    } else {
+        return ok(json!("未改变任何数据"));
+    }
+}
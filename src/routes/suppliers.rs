@@ -0,0 +1,135 @@
+use crate::models::Offer;
+use crate::sourcing;
+use crate::suppliers::Supplier;
+use crate::types::{err, ok, AEState, AeError, Res};
+use axum::extract::{Json, Path, State};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{query, query_as, QueryBuilder};
+use std::cmp::{max, min};
+use std::collections::HashSet;
+
+#[derive(Deserialize)]
+pub struct SSReq {
+    page: i64,
+    per_page: i64,
+    name: String,
+    blacklisted: i64,
+}
+pub async fn admin_supplier_show(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Json(mut search): Json<SSReq>,
+) -> Result<Res, AeError> {
+    let mut total_query_builder = QueryBuilder::new("select count(id) from suppliers where 1=1 ");
+    let mut suppliers_query_builder = QueryBuilder::new("select * from suppliers where 1=1 ");
+
+    if !search.name.trim().is_empty() {
+        total_query_builder.push(" and name like ");
+        total_query_builder.push_bind(format!("%{}%", search.name.trim()));
+        suppliers_query_builder.push(" and name like ");
+        suppliers_query_builder.push_bind(format!("%{}%", search.name.trim()));
+    }
+    if search.blacklisted > -1 {
+        total_query_builder.push(" and blacklisted = ");
+        total_query_builder.push_bind(search.blacklisted);
+        suppliers_query_builder.push(" and blacklisted = ");
+        suppliers_query_builder.push_bind(search.blacklisted);
+    }
+
+    let total: (i64,) = total_query_builder.build_query_as().fetch_one(&db).await?;
+    search.per_page = if search.per_page == 0 {
+        20
+    } else {
+        search.per_page
+    };
+    search.page = max(1, min(search.page, total.0 / search.per_page + 1));
+    suppliers_query_builder.push(" order by score asc, sale30 desc");
+    suppliers_query_builder.push(" limit ");
+    suppliers_query_builder.push_bind(search.per_page);
+    suppliers_query_builder.push(" offset ");
+    suppliers_query_builder.push_bind((search.page - 1) * search.per_page);
+
+    let suppliers: Vec<Supplier> = suppliers_query_builder
+        .build_query_as()
+        .fetch_all(&db)
+        .await?;
+
+    return ok(json!({
+        "page": search.page,
+        "per_page": search.per_page,
+        "total": total.0,
+        "suppliers": suppliers,
+    }));
+}
+
+//拉黑后该供货商的offer不再作为主offer, 以它为主offer的product立即重新选择, 返回切换的(product_id, 新的主offer)
+pub async fn admin_supplier_blacklist(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path((id, tf)): Path<(i64, bool)>,
+) -> Result<Res, AeError> {
+    let mut db_trans = db.begin().await?;
+    if query("update suppliers set blacklisted=? where id=?")
+        .bind(tf)
+        .bind(id)
+        .execute(&mut *db_trans)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return ok(json!("未改变任何数据"));
+    }
+    let mut switched = vec![];
+    if tf {
+        let product_ids: Vec<(i64,)> = query_as("select distinct l.product_id from offer_products l join offers o on o.offer_id=l.offer_id join suppliers s on s.name=o.supplier where s.id=? and l.is_primary=1")
+            .bind(id)
+            .fetch_all(&mut *db_trans)
+            .await?;
+        for (product_id,) in product_ids {
+            if let Some(to) = sourcing::reselect(
+                &mut db_trans,
+                product_id,
+                sourcing::BLACKLISTED,
+                &json!({ "supplier_id": id }),
+                &HashSet::new(),
+            )
+            .await?
+            {
+                switched.push((product_id, to));
+            }
+        }
+    }
+    db_trans.commit().await?;
+    return ok(json!(switched));
+}
+
+//供货商的所有offer
+pub async fn admin_supplier_offers(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(id): Path<i64>,
+) -> Result<Res, AeError> {
+    let supplier_: Option<Supplier> = query_as("select * from suppliers where id=?")
+        .bind(id)
+        .fetch_optional(&db)
+        .await?;
+    if let Some(supplier) = supplier_ {
+        let offers: Vec<Offer> = query_as("select * from offers where supplier=? order by id desc")
+            .bind(&supplier.name)
+            .fetch_all(&db)
+            .await?;
+        return ok(json!({
+            "supplier": supplier,
+            "offers": offers,
+        }));
+    } else {
+        return err("not found".to_string());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::analytics;
+use crate::tracking::{self, Tracking};
+use crate::types::{err, ok, AEState, AeError, Res};
+use axum::extract::{Json, Path, Query, State};
+use serde::Deserialize;
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+
+//采集物流轨迹
+pub async fn tracking(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Json(tracking): Json<Tracking>,
+) -> Result<Res, AeError> {
+    match tracking::record(&db, &tracking).await? {
+        Some(added) => {
+            return ok(json!(added));
+        }
+        None => {
+            return err("没有该订单".to_string());
+        }
+    }
+}
+
+pub async fn admin_tracking_events(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Path(order_id): Path<i64>,
+) -> Result<Res, AeError> {
+    return ok(json!(tracking::events(&db, order_id).await?));
+}
+
+#[derive(Deserialize)]
+pub struct StuckReq {
+    //默认为TRACKING_STUCK_DAYS
+    days: Option<i64>,
+    limit: Option<i64>,
+}
+//长时间没有物流更新的包裹
+pub async fn admin_tracking_stuck(
+    State(AEState {
+        db_pool: db,
+        settings,
+    }): State<AEState>,
+    Query(req): Query<StuckReq>,
+) -> Result<Res, AeError> {
+    let days = req.days.unwrap_or(settings.tracking_stuck_days);
+    let stuck = tracking::stuck(
+        &db,
+        days,
+        settings.tracking_stuck_max_days,
+        req.limit.unwrap_or(100),
+    )
+    .await?;
+    return ok(json!({
+        "days": days,
+        "orders": stuck,
+    }));
+}
+
+#[derive(Deserialize)]
+pub struct TransitReq {
+    //签收日期 "YYYY-MM-DD"或"YYYY-MM-DD~YYYY-MM-DD", 默认最近90天
+    date: Option<String>,
+}
+//按目的地统计的运输天数
+pub async fn admin_tracking_transit(
+    State(AEState {
+        db_pool: db,
+        settings: _,
+    }): State<AEState>,
+    Query(req): Query<TransitReq>,
+) -> Result<Res, AeError> {
+    let range = match req.date.as_deref().map(|d| d.trim()) {
+        Some(date) if !date.is_empty() => analytics::parse_date_range(date)?,
+        _ => {
+            let today = OffsetDateTime::now_local()?.date();
+            (today - Duration::days(89), today)
+        }
+    };
+    return ok(json!({
+        "from": range.0.to_string(),
+        "to": range.1.to_string(),
+        "destinations": tracking::transit(&db, range).await?,
+    }));
+}
@@ -0,0 +1,146 @@
+use crate::analytics::DateRange;
+use crate::config::Settings;
+use crate::normalize::Normalizer;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{from_str, json, Value};
+use sqlx::{query, query_as, QueryBuilder, SqlitePool};
+use std::collections::{BTreeMap, HashMap};
+use time::Duration;
+
+//统计周期, 按下单时间(orders.created_at)的本地日期
+pub const DAY: &str = "day";
+pub const WEEK: &str = "week";
+pub const MONTH: &str = "month";
+
+fn period_expr(period: &str) -> Result<&'static str> {
+    match period {
+        DAY => Ok("substr(created_at,1,10)"),
+        WEEK => Ok("strftime('%Y-W%W',substr(created_at,1,10))"),
+        MONTH => Ok("substr(created_at,1,7)"),
+        _ => Err(anyhow!("统计周期只能是day, week或month")),
+    }
+}
+
+//归档订单前按下单日期/商品/sku汇总到archived_sales, ?1为截止日期, 同一天分多次归档时累加
+pub const ARCHIVE_SQL: &str = "insert into archived_sales (date,product_id,sku,qty,revenue,priced_qty) select substr(created_at,1,10), product_id, sku, sum(qty), sum(qty*price), sum(case when price>0 then qty else 0 end) from order_items where order_id in (select order_id from main.orders where created_at < ?1) group by 1,2,3 on conflict(date,product_id,sku) do update set qty=qty+excluded.qty,revenue=revenue+excluded.revenue,priced_qty=priced_qty+excluded.priced_qty,created_at=excluded.created_at";
+
+//order_items加上已归档的汇总, created_at为下单时间或日期
+const SALE_ROWS: &str = "(select created_at, product_id, sku, qty, qty*price as revenue, case when price>0 then qty else 0 end as priced_qty from order_items union all select date, product_id, sku, qty, revenue, priced_qty from archived_sales)";
+
+#[derive(Serialize, Default)]
+pub struct SaleStat {
+    pub period: String,
+    pub product_id: i64,
+    pub color: String,
+    pub size: String,
+    pub units: i64,
+    pub revenue: i64,      //有单价的商品行的销售额
+    pub priced_units: i64, //有单价的数量
+}
+
+//按周期和商品(by_sku时再按sku)统计销量, 数据来自order_items和已归档的汇总
+pub async fn stats(
+    db: &SqlitePool,
+    norm: &Normalizer,
+    period: &str,
+    product_id: Option<i64>,
+    range: DateRange,
+    by_sku: bool,
+) -> Result<Vec<SaleStat>> {
+    let mut query_builder = QueryBuilder::new(format!(
+        "select {} as period, product_id, sku, sum(qty), sum(revenue), sum(priced_qty) from {} where created_at >= ",
+        period_expr(period)?,
+        SALE_ROWS
+    ));
+    query_builder.push_bind(range.0);
+    query_builder.push(" and created_at < ");
+    query_builder.push_bind(range.1 + Duration::days(1));
+    if let Some(product_id) = product_id {
+        query_builder.push(" and product_id = ");
+        query_builder.push_bind(product_id);
+    }
+    query_builder.push(" group by 1, 2, 3");
+    let rows: Vec<(String, i64, String, i64, i64, i64)> =
+        query_builder.build_query_as().fetch_all(db).await?;
+
+    //sku文本规范化后可能相同, 合并
+    let mut stats: BTreeMap<(String, i64, String, String), SaleStat> = BTreeMap::new();
+    for (period, product_id, sku, units, revenue, priced_units) in rows {
+        let (color, size) = if by_sku {
+            norm.sku(&sku)
+        } else {
+            (String::new(), String::new())
+        };
+        let stat = stats
+            .entry((period.clone(), product_id, color.clone(), size.clone()))
+            .or_insert_with(|| SaleStat {
+                period,
+                product_id,
+                color,
+                size,
+                ..Default::default()
+            });
+        stat.units += units;
+        stat.revenue += revenue;
+        stat.priced_units += priced_units;
+    }
+    Ok(stats.into_values().collect())
+}
+
+//按订单和已归档的汇总重新计算products.sale_count和sale_info, sale_info保留库存中有的sku(数量为0)
+//未归档直接删除的订单不计入
+pub async fn recompute(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let norm = Normalizer::from_settings(settings);
+    let rows: Vec<(i64, String, i64)> = query_as(&format!(
+        "select product_id, sku, sum(qty) from {} group by product_id, sku",
+        SALE_ROWS
+    ))
+    .fetch_all(db)
+    .await?;
+    let mut sold: HashMap<i64, Vec<(String, i64)>> = HashMap::new();
+    for (product_id, sku, qty) in rows {
+        sold.entry(product_id).or_default().push((sku, qty));
+    }
+
+    let products: Vec<(i64, i64, i64, String, String)> =
+        query_as("select id, product_id, sale_count, sale_info, stock_info from products")
+            .fetch_all(db)
+            .await?;
+    let mut changed = 0;
+    let mut db_trans = db.begin().await?;
+    for (id, product_id, sale_count, sale_info, stock_info) in products.iter() {
+        let mut info = norm.sku_map(&from_str(stock_info).unwrap_or(json!({})));
+        if let Some(colors) = info.as_object_mut() {
+            for sizes in colors.values_mut() {
+                if let Some(sizes) = sizes.as_object_mut() {
+                    sizes.values_mut().for_each(|n| *n = json!(0));
+                }
+            }
+        }
+        let mut count = 0;
+        for (sku, qty) in sold.remove(product_id).unwrap_or_default() {
+            let (color, size) = norm.sku(&sku);
+            let n = info[&color][&size].as_i64().unwrap_or(0);
+            info[&color][&size] = json!(n + qty);
+            count += qty;
+        }
+        let old_info = norm.sku_map(&from_str(sale_info).unwrap_or(json!({})));
+        if count != *sale_count || info != old_info {
+            query("update products set sale_count=?,sale_info=? where id=?")
+                .bind(count)
+                .bind(info.to_string())
+                .bind(id)
+                .execute(&mut *db_trans)
+                .await?;
+            changed += 1;
+        }
+    }
+    db_trans.commit().await?;
+    Ok(json!({
+        "products": products.len(),
+        "changed": changed,
+        //订单中有但products中没有的商品
+        "unknown_products": sold.len(),
+    }))
+}
@@ -0,0 +1,272 @@
+use crate::config::{Settings, SharedSettings};
+use crate::jobs;
+use crate::maintenance;
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use sqlx::{query_as, SqlitePool};
+use time::{Duration, OffsetDateTime};
+use tokio::time::sleep;
+use tracing::{error, info};
+
+//cron表达式: 分 时 日 月 周, 支持 * 数字 列表(,) 范围(-) 步长(/), 周日为0
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expr: String,
+    minute: u64,
+    hour: u64,
+    day: u64,
+    month: u64,
+    weekday: u64,
+    day_any: bool,
+    weekday_any: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(anyhow!("步长不能为0: {}", part));
+        }
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (a.parse()?, b.parse()?)
+        } else {
+            let n = range.parse()?;
+            (n, if part.contains('/') { max } else { n })
+        };
+        if from < min || to > max || from > to {
+            return Err(anyhow!("超出范围{}-{}: {}", min, max, part));
+        }
+        for n in (from..=to).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("cron表达式需要5个字段: {}", expr));
+        }
+        let weekday = parse_field(fields[4], 0, 7)?;
+        let weekday = (weekday | (weekday >> 7)) & 0x7f; //7也表示周日
+        Ok(Self {
+            expr: expr.to_string(),
+            minute: parse_field(fields[0], 0, 59)?,
+            hour: parse_field(fields[1], 0, 23)?,
+            day: parse_field(fields[2], 1, 31)?,
+            month: parse_field(fields[3], 1, 12)?,
+            weekday,
+            day_any: fields[2] == "*",
+            weekday_any: fields[4] == "*",
+        })
+    }
+
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+
+    pub fn matches(&self, t: OffsetDateTime) -> bool {
+        let day = self.day & (1 << t.day()) > 0;
+        let weekday = self.weekday & (1 << t.weekday().number_days_from_sunday()) > 0;
+        //日和周都有限制时, 满足其一即可(与cron一致)
+        let date_ok = match (self.day_any, self.weekday_any) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        date_ok
+            && self.minute & (1 << t.minute()) > 0
+            && self.hour & (1 << t.hour()) > 0
+            && self.month & (1 << u8::from(t.month())) > 0
+    }
+
+    //下一次执行时间, 一年内没有则为None
+    pub fn next_after(&self, t: OffsetDateTime) -> Option<OffsetDateTime> {
+        let mut t = t.replace_second(0).ok()?.replace_nanosecond(0).ok()? + Duration::minutes(1);
+        let end = t + Duration::days(366);
+        while t < end {
+            if self.matches(t) {
+                return Some(t);
+            }
+            t += Duration::minutes(1);
+        }
+        None
+    }
+}
+
+//默认计划, 可在settings.SCHEDULE中覆盖, 设置为""则不自动执行
+fn default_cron(task: &str) -> &'static str {
+    match task {
+        maintenance::PURGE_OFFERS => "30 3 * * *",
+        maintenance::PURGE_PRODUCTS => "35 3 * * *",
+        maintenance::PURGE_ORDERS => "40 3 * * *",
+        maintenance::DAILY_SNAPSHOT => "55 23 * * *",
+        maintenance::RECOMPUTE_WEIGHTS => "0 4 * * 1",
+        maintenance::BACKUP => "0 3 * * *",
+        maintenance::REFRESH_SUPPLIERS => "20 4 * * *",
+        //会改写所有商品的销量记录并标记下架, 没有预览, 默认只手动执行
+        maintenance::RECOMPUTE_TRAFFIC => "",
+        //订单会被清理, 默认只手动执行
+        maintenance::RECOMPUTE_SALES => "",
+        _ => "",
+    }
+}
+
+//各任务的计划, 未配置的返回None
+pub fn schedule(settings: &Settings) -> Vec<(&'static str, Result<Option<Cron>>)> {
+    maintenance::TASKS
+        .iter()
+        .map(|task| {
+            let expr = settings
+                .schedule
+                .get(*task)
+                .map(|e| e.as_str())
+                .unwrap_or(default_cron(task))
+                .trim();
+            let cron = if expr.is_empty() {
+                Ok(None)
+            } else {
+                Cron::parse(expr).map(Some)
+            };
+            (*task, cron)
+        })
+        .collect()
+}
+
+//启动任务, 同类任务正在执行时不重复启动
+pub async fn run_task(db: &SqlitePool, settings: &Settings, task: &str) -> Result<i64> {
+    let task = match maintenance::TASKS.iter().find(|t| **t == task) {
+        Some(t) => *t,
+        None => {
+            return Err(anyhow!("没有该任务: {}", task));
+        }
+    };
+    let running: Option<(i64,)> =
+        query_as("select id from jobs where kind=? and status='running' limit 1")
+            .bind(task)
+            .fetch_optional(db)
+            .await?;
+    if let Some((id,)) = running {
+        return Err(anyhow!("任务正在执行中, 任务id: {}", id));
+    }
+    let job_db = db.clone();
+    let settings = settings.clone();
+    jobs::start(db, task, json!({}), move |job| async move {
+        maintenance::run(&job_db, &settings, task, &job).await
+    })
+    .await
+}
+
+//计划有误的任务不执行, 启动和重新加载配置时已检查
+fn tasks(settings: &Settings) -> Vec<(&'static str, Cron)> {
+    schedule(settings)
+        .into_iter()
+        .filter_map(|(task, cron)| match cron {
+            Ok(Some(cron)) => Some((task, cron)),
+            Ok(None) => None,
+            Err(e) => {
+                error!("schedule of {} ignored: {:#}", task, e);
+                None
+            }
+        })
+        .collect()
+}
+
+//每分钟检查一次计划, 时间到了就启动任务, 每次使用当时的settings
+pub fn spawn(db: SqlitePool, settings: SharedSettings) {
+    for (task, cron) in tasks(&settings.get()) {
+        info!("schedule {}: {}", task, cron.expr);
+    }
+    tokio::spawn(async move {
+        loop {
+            let now = match OffsetDateTime::now_local() {
+                Ok(now) => now,
+                Err(e) => {
+                    error!("scheduler stopped: {}", e);
+                    return;
+                }
+            };
+            let current = settings.get();
+            for (task, cron) in tasks(&current) {
+                if cron.matches(now) {
+                    match run_task(&db, &current, task).await {
+                        Ok(id) => info!("scheduled task {} started, job id: {}", task, id),
+                        Err(e) => error!("scheduled task {} not started: {:#}", task, e),
+                    }
+                }
+            }
+            //等到下一分钟开始
+            sleep(std::time::Duration::from_secs(60 - now.second() as u64)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month, Time};
+
+    fn at(month: Month, day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        Date::from_calendar_date(2026, month, day)
+            .unwrap()
+            .with_time(Time::from_hms(hour, minute, 0).unwrap())
+            .assume_utc()
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(parse_field("*", 0, 3).unwrap(), 0b1111);
+        assert_eq!(parse_field("1,3", 0, 5).unwrap(), 0b1010);
+        assert_eq!(parse_field("2-4", 0, 5).unwrap(), 0b11100);
+        assert_eq!(parse_field("*/2", 0, 5).unwrap(), 0b10101);
+        assert_eq!(parse_field("1/2", 0, 5).unwrap(), 0b101010);
+        assert_eq!(parse_field("0-4/3,5", 0, 5).unwrap(), 0b101001);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_field("6", 0, 5).is_err());
+        assert!(parse_field("0", 1, 5).is_err());
+        assert!(parse_field("4-2", 0, 5).is_err());
+        assert!(parse_field("*/0", 0, 5).is_err());
+        assert!(parse_field("a", 0, 5).is_err());
+        assert!(Cron::parse("0 3 * *").is_err());
+        assert!(Cron::parse("60 3 * * *").is_err());
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        let sunday = at(Month::October, 18, 3, 0);
+        assert!(Cron::parse("0 3 * * 0").unwrap().matches(sunday));
+        assert!(Cron::parse("0 3 * * 7").unwrap().matches(sunday));
+        assert!(!Cron::parse("0 3 * * 1-6").unwrap().matches(sunday));
+    }
+
+    #[test]
+    fn day_or_weekday() {
+        //日和周都有限制时满足其一即可, 只限制一个时必须满足
+        let cron = Cron::parse("0 3 1 * 1").unwrap();
+        assert!(cron.matches(at(Month::November, 1, 3, 0)));
+        assert!(cron.matches(at(Month::October, 19, 3, 0)));
+        assert!(!cron.matches(at(Month::October, 20, 3, 0)));
+        let cron = Cron::parse("0 3 1 * *").unwrap();
+        assert!(!cron.matches(at(Month::October, 19, 3, 0)));
+    }
+
+    #[test]
+    fn next_after() {
+        let cron = Cron::parse("30 3 * * *").unwrap();
+        let t = at(Month::October, 19, 3, 30) + Duration::seconds(10);
+        assert_eq!(cron.next_after(t), Some(at(Month::October, 20, 3, 30)));
+        let t = at(Month::October, 19, 3, 29) + Duration::seconds(59);
+        assert_eq!(cron.next_after(t), Some(at(Month::October, 19, 3, 30)));
+        assert_eq!(Cron::parse("0 0 31 2 *").unwrap().next_after(t), None);
+    }
+}
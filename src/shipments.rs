@@ -0,0 +1,143 @@
+use crate::leases;
+use crate::order_items;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{from_str, to_string};
+use sqlx::{query, query_as, FromRow, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+
+//订单的一个物流包裹, 一个订单可以分多个包裹发货
+#[derive(Serialize, FromRow)]
+pub struct Shipment {
+    pub id: i64,
+    pub order_id: i64,
+    pub lg_order_id: String,
+    pub weight: i64,
+    pub items: String,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "show_time")]
+    pub updated_at: OffsetDateTime,
+}
+
+//添加包裹, 已有时只更新包裹内的订单行(items不为空时)
+pub async fn add(
+    conn: &mut SqliteConnection,
+    order_id: i64,
+    lg_order_id: &str,
+    items: &[(i64, i64)],
+) -> Result<u64> {
+    let now = OffsetDateTime::now_local()?;
+    Ok(query("insert into shipments (order_id,lg_order_id,items,created_at,updated_at) values (?,?,?,?,?) on conflict(order_id,lg_order_id) do update set items=excluded.items where excluded.items!='[]'")
+        .bind(order_id)
+        .bind(lg_order_id)
+        .bind(to_string(items)?)
+        .bind(now)
+        //updated_at为创建时间, 同orders, 当天添加的包裹不会马上统计重量
+        .bind(now)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected())
+}
+
+//整单发货的物流单号更换后, 删除旧包裹及其租约(已统计重量的也删除, 订单重量不再计入), 同orders.lg_order_id只保留最大的
+//已有更大的整单物流单号时, 该单号已被替换, 返回false
+pub async fn supersede(
+    conn: &mut SqliteConnection,
+    order_id: i64,
+    lg_order_id: &str,
+) -> Result<bool> {
+    let newer: Option<(i64,)> = query_as(
+        "select id from shipments where order_id=? and items='[]' and lg_order_id>? limit 1",
+    )
+    .bind(order_id)
+    .bind(lg_order_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if newer.is_some() {
+        return Ok(false);
+    }
+    let old = "select id from shipments where order_id=?1 and items='[]' and lg_order_id<?2";
+    query(&format!(
+        "delete from leases where kind=?3 and item_id in ({})",
+        old
+    ))
+    .bind(order_id)
+    .bind(lg_order_id)
+    .bind(leases::SHIPMENT)
+    .execute(&mut *conn)
+    .await?;
+    let removed = query(&format!("delete from shipments where id in ({})", old))
+        .bind(order_id)
+        .bind(lg_order_id)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+    if removed > 0 {
+        //订单重量为各包裹重量之和, 同update_weight
+        query("update orders set weight=(select coalesce(sum(weight),0) from shipments where order_id=?1) where order_id=?1")
+            .bind(order_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(true)
+}
+
+pub async fn of_order(conn: &mut SqliteConnection, order_id: i64) -> Result<Vec<Shipment>> {
+    Ok(
+        query_as("select * from shipments where order_id=? order by id asc")
+            .bind(order_id)
+            .fetch_all(&mut *conn)
+            .await?,
+    )
+}
+
+//包裹内的商品 [(product_id, qty)]
+//没有记录包裹内的订单行时, 只有一个包裹的订单为整单, 多个包裹的无法确定返回None
+pub async fn parcel_items(
+    conn: &mut SqliteConnection,
+    shipment: &Shipment,
+) -> Result<Option<Vec<(i64, i64)>>> {
+    let order_lines = order_items::of_orders(&mut *conn, &[shipment.order_id]).await?;
+    let lines: Vec<(i64, i64)> = from_str(&shipment.items).unwrap_or_default();
+    if lines.is_empty() {
+        let (count,): (i64,) = query_as("select count(id) from shipments where order_id=?")
+            .bind(shipment.order_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        if count > 1 {
+            return Ok(None);
+        }
+        return Ok(Some(
+            order_lines
+                .iter()
+                .map(|item| (item.product_id, item.qty))
+                .collect(),
+        ));
+    }
+    let products: HashMap<i64, i64> = order_lines
+        .iter()
+        .map(|item| (item.line_id, item.product_id))
+        .collect();
+    let mut items = vec![];
+    for (line_id, qty) in lines {
+        match products.get(&line_id) {
+            Some(product_id) => items.push((*product_id, qty)),
+            None => {
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(items))
+}
+
+//删除已不存在的订单的包裹
+pub async fn purge(db: &SqlitePool) -> Result<u64> {
+    Ok(
+        query("delete from shipments where order_id not in (select order_id from orders)")
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
@@ -0,0 +1,222 @@
+use crate::models::{Offer, Product};
+use crate::normalize::Normalizer;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{from_str, json, Value};
+use sqlx::{query, query_as, FromRow, SqlitePool};
+use std::collections::{BTreeSet, HashMap};
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+
+//ae颜色/尺码与1688 sku的对应关系, 按product和offer分别记录, 切换主offer后使用新offer的
+#[derive(Serialize, FromRow, Clone)]
+pub struct SkuMapping {
+    pub id: i64,
+    pub product_id: i64,
+    pub ae_color: String,
+    pub ae_size: String,
+    pub offer_id: i64,
+    pub offer_color: String,
+    pub offer_size: String,
+    pub sku_id: String,
+    pub manual: i64,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "show_time")]
+    pub updated_at: OffsetDateTime,
+}
+
+//ae颜色名中的数字是1688颜色的序号, 从1开始
+pub fn color_index(ae_color: &str) -> Option<usize> {
+    let reg = Regex::new(r"[^\d]").ok()?;
+    reg.replace_all(ae_color, "")
+        .parse::<usize>()
+        .ok()?
+        .checked_sub(1)
+}
+
+//1688的颜色和尺码名称
+pub fn sku_props(offer: &Offer) -> (Vec<String>, Vec<String>) {
+    let sku_info: Value = from_str(&offer.sku_info_use).unwrap_or(json!({}));
+    let names = |i: usize| -> Vec<String> {
+        sku_info["skuProps"][i]["value"]
+            .as_array()
+            .map(|v| {
+                v.iter()
+                    .map(|n| n["name"].as_str().unwrap_or("").to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    (names(0), names(1))
+}
+
+//1688 sku的key为"颜色&gt;尺码", 只有颜色时没有分隔符, 按规范化后的名称完全相同查找
+fn find_sku_id(
+    norm: &Normalizer,
+    sku_ids: &[String],
+    offer_color: &str,
+    offer_size: &str,
+) -> String {
+    sku_ids
+        .iter()
+        .find(|k| {
+            let (color, size) = k.split_once("&gt;").unwrap_or((k, ""));
+            norm.color(color) == norm.color(offer_color) && norm.size(size) == norm.size(offer_size)
+        })
+        .cloned()
+        .unwrap_or_default()
+}
+
+//按规范化后的名称推荐对应关系, 颜色名称对不上时按颜色序号, 返回(ae颜色, ae尺码, 1688颜色, 1688尺码, sku)
+pub fn suggest(
+    norm: &Normalizer,
+    product: &Product,
+    offer: &Offer,
+) -> Vec<(String, String, String, String, String)> {
+    let mut ae_skus: BTreeSet<(String, String)> = BTreeSet::new();
+    for info in [&product.sale_info, &product.stock_info] {
+        let info = norm.sku_map(&from_str(info).unwrap_or(json!({})));
+        if let Some(colors) = info.as_object() {
+            for (color, sizes) in colors {
+                if let Some(sizes) = sizes.as_object() {
+                    for size in sizes.keys() {
+                        ae_skus.insert((color.clone(), size.clone()));
+                    }
+                }
+            }
+        }
+    }
+    let (colors, sizes) = sku_props(offer);
+    let sale_info: Value = from_str(&offer.sale_info).unwrap_or(json!({}));
+    let sku_ids: Vec<String> = sale_info["detail"]
+        .as_object()
+        .map(|d| d.keys().cloned().collect())
+        .unwrap_or_default();
+
+    ae_skus
+        .into_iter()
+        .map(|(ae_color, ae_size)| {
+            let offer_color = colors
+                .iter()
+                .find(|c| norm.color(c) == ae_color)
+                .or_else(|| color_index(&ae_color).and_then(|i| colors.get(i)))
+                .cloned()
+                .unwrap_or_default();
+            let offer_size = sizes
+                .iter()
+                .find(|s| norm.size(s) == ae_size)
+                .cloned()
+                .unwrap_or_default();
+            let sku_id = if offer_color.is_empty() {
+                String::new()
+            } else {
+                find_sku_id(norm, &sku_ids, &offer_color, &offer_size)
+            };
+            (ae_color, ae_size, offer_color, offer_size, sku_id)
+        })
+        .collect()
+}
+
+//重新推荐, 手动设置过的不覆盖
+pub async fn suggest_for(
+    db: &SqlitePool,
+    norm: &Normalizer,
+    product_id: i64,
+    offer_id: i64,
+) -> Result<u64> {
+    let product: Option<Product> = query_as("select * from products where product_id=?")
+        .bind(product_id)
+        .fetch_optional(db)
+        .await?;
+    let offer: Option<Offer> = query_as("select * from offers where offer_id=?")
+        .bind(offer_id)
+        .fetch_optional(db)
+        .await?;
+    let (product, offer) = match (product, offer) {
+        (Some(p), Some(o)) => (p, o),
+        _ => {
+            return Err(anyhow!("没有该product或offer"));
+        }
+    };
+    let now = OffsetDateTime::now_local()?;
+    let mut changed = 0;
+    let mut db_trans = db.begin().await?;
+    for s in suggest(norm, &product, &offer) {
+        changed += query("insert into sku_mappings (product_id,ae_color,ae_size,offer_id,offer_color,offer_size,sku_id,manual,created_at,updated_at) values (?,?,?,?,?,?,?,0,?,?) on conflict(product_id,offer_id,ae_color,ae_size) do update set offer_color=excluded.offer_color,offer_size=excluded.offer_size,sku_id=excluded.sku_id,updated_at=excluded.updated_at where manual=0 and (offer_color!=excluded.offer_color or offer_size!=excluded.offer_size or sku_id!=excluded.sku_id)")
+            .bind(product_id)
+            .bind(&s.0)
+            .bind(&s.1)
+            .bind(offer_id)
+            .bind(&s.2)
+            .bind(&s.3)
+            .bind(&s.4)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *db_trans)
+            .await?
+            .rows_affected();
+    }
+    db_trans.commit().await?;
+    Ok(changed)
+}
+
+//product在该offer下的对应关系, 还没有时先自动推荐
+pub async fn mappings(
+    db: &SqlitePool,
+    norm: &Normalizer,
+    product_id: i64,
+    offer_id: i64,
+) -> Result<Vec<SkuMapping>> {
+    let sql =
+        "select * from sku_mappings where product_id=? and offer_id=? order by ae_color, ae_size";
+    let mut maps: Vec<SkuMapping> = query_as(sql)
+        .bind(product_id)
+        .bind(offer_id)
+        .fetch_all(db)
+        .await?;
+    if maps.is_empty() && offer_id > 0 {
+        suggest_for(db, norm, product_id, offer_id).await?;
+        maps = query_as(sql)
+            .bind(product_id)
+            .bind(offer_id)
+            .fetch_all(db)
+            .await?;
+    }
+    Ok(maps)
+}
+
+//按(ae颜色, ae尺码)查找
+pub type SkuMap = HashMap<(String, String), SkuMapping>;
+
+pub fn by_sku(maps: Vec<SkuMapping>) -> SkuMap {
+    maps.into_iter()
+        .map(|m| ((m.ae_color.clone(), m.ae_size.clone()), m))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sku_ids(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|k| k.to_string()).collect()
+    }
+
+    #[test]
+    fn sku_id_matches_whole_names() {
+        let norm = Normalizer::default();
+        let ids = sku_ids(&["红&gt;2XL", "酒红&gt;XL", "红&gt;XL", "红&gt;XXL"]);
+        assert_eq!(find_sku_id(&norm, &ids, "红", "XL"), "红&gt;XL");
+        assert_eq!(find_sku_id(&norm, &ids, "酒红", "xl"), "酒红&gt;XL");
+        assert_eq!(find_sku_id(&norm, &ids, "红", ""), "");
+        assert_eq!(find_sku_id(&norm, &ids, "红", "L"), "");
+    }
+
+    #[test]
+    fn sku_id_without_size() {
+        let norm = Normalizer::default();
+        let ids = sku_ids(&["酒红", "红"]);
+        assert_eq!(find_sku_id(&norm, &ids, "红", ""), "红");
+    }
+}
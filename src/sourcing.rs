@@ -0,0 +1,158 @@
+use crate::links;
+use crate::models::Offer;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{from_str, json, Value};
+use sqlx::{query, query_as, FromRow, Row, SqliteConnection, SqlitePool};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+
+//切换主offer的原因
+pub const DELETED: &str = "deleted";
+pub const UNAVAILABLE: &str = "unavailable";
+pub const PRICE_RAISED: &str = "price_raised";
+pub const SKU_LOST: &str = "sku_lost";
+pub const RESELECT: &str = "reselect";
+pub const BLACKLISTED: &str = "blacklisted";
+
+#[derive(Serialize, FromRow)]
+pub struct Switch {
+    pub id: i64,
+    pub product_id: i64,
+    pub from_offer_id: i64,
+    pub to_offer_id: i64,
+    pub reason: String,
+    pub detail: String,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+}
+
+//可订数量中有库存的sku, 格式同offer的sale_info
+pub fn in_stock(can_book_amount: &Value) -> HashSet<String> {
+    match can_book_amount["detail"].as_object() {
+        Some(detail) => detail
+            .iter()
+            .filter(|(_, v)| v.as_i64().unwrap_or(0) > 0)
+            .map(|(k, _)| k.clone())
+            .collect(),
+        None => HashSet::new(),
+    }
+}
+
+//sale_record最后一项是最近一次采集到的可订数量
+pub fn stock_skus(sale_record: &str) -> HashSet<String> {
+    let records: Value = from_str(sale_record).unwrap_or(json!([]));
+    match records.as_array().and_then(|r| r.last()) {
+        Some(last) => in_stock(last),
+        None => HashSet::new(),
+    }
+}
+
+//未删除, 未失效, 且有库存
+fn usable(offer: &Offer) -> bool {
+    offer.deleted_at.is_none()
+        && offer.unavailable_at.is_none()
+        && !stock_skus(&offer.sale_record).is_empty()
+}
+
+//价格低的优先, 其次商家销量高的
+fn better(a: &Offer, b: &Offer) -> Ordering {
+    a.better_price
+        .cmp(&b.better_price)
+        .then(b.sale30.cmp(&a.sale30))
+        .then(a.id.cmp(&b.id))
+}
+
+//按原因重新选择主offer, 切换了返回新的主offer
+//required为备用offer必须有库存的sku
+pub async fn reselect(
+    conn: &mut SqliteConnection,
+    product_id: i64,
+    reason: &str,
+    detail: &Value,
+    required: &HashSet<String>,
+) -> Result<Option<i64>> {
+    let rows = query("select o.*, l.is_primary, coalesce(s.blacklisted,0) as blacklisted from offer_products l join offers o on o.offer_id=l.offer_id left join suppliers s on s.name=o.supplier where l.product_id=?")
+        .bind(product_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut current: Option<Offer> = None;
+    let mut current_blacklisted = false;
+    let mut others: Vec<Offer> = vec![];
+    for row in rows.iter() {
+        let offer = Offer::from_row(row)?;
+        let blacklisted = row.try_get::<i64, _>("blacklisted")? == 1;
+        if row.try_get::<i64, _>("is_primary")? == 1 {
+            current = Some(offer);
+            current_blacklisted = blacklisted;
+        } else if !blacklisted {
+            //拉黑的供货商不作为备用
+            others.push(offer);
+        }
+    }
+    let best = match others
+        .iter()
+        .filter(|o| usable(o) && required.is_subset(&stock_skus(&o.sale_record)))
+        .min_by(|a, b| better(a, b))
+    {
+        Some(best) => best,
+        None => {
+            return Ok(None);
+        }
+    };
+    //提价和手动重选时, 原主offer仍可用、未拉黑且不比备用差就不切换
+    let switch = match (reason, &current) {
+        (PRICE_RAISED | RESELECT, Some(cur)) => {
+            current_blacklisted || !usable(cur) || better(best, cur) == Ordering::Less
+        }
+        _ => true,
+    };
+    if !switch {
+        return Ok(None);
+    }
+
+    let from_offer_id = current.as_ref().map(|c| c.offer_id).unwrap_or(0);
+    let mut detail = detail.clone();
+    detail["from_price"] = json!(current.as_ref().map(|c| c.better_price));
+    detail["to_price"] = json!(best.better_price);
+    detail["to_sale30"] = json!(best.sale30);
+    query("insert into offer_switches (product_id,from_offer_id,to_offer_id,reason,detail,created_at) values (?,?,?,?,?,?)")
+        .bind(product_id)
+        .bind(from_offer_id)
+        .bind(best.offer_id)
+        .bind(reason)
+        .bind(detail.to_string())
+        .bind(OffsetDateTime::now_local()?)
+        .execute(&mut *conn)
+        .await?;
+    links::set_primary(conn, product_id, best.offer_id).await?;
+    Ok(Some(best.offer_id))
+}
+
+//offer变化后, 对以它为主offer的product重新选择, 返回(product_id, 新的主offer)
+pub async fn on_offer_change(
+    db: &SqlitePool,
+    offer_id: i64,
+    reason: &str,
+    detail: Value,
+    required: &HashSet<String>,
+) -> Result<Vec<(i64, i64)>> {
+    let product_ids: Vec<(i64,)> =
+        query_as("select product_id from offer_products where offer_id=? and is_primary=1")
+            .bind(offer_id)
+            .fetch_all(db)
+            .await?;
+    let mut switched = vec![];
+    if product_ids.is_empty() {
+        return Ok(switched);
+    }
+    let mut db_trans = db.begin().await?;
+    for (product_id,) in product_ids {
+        if let Some(to) = reselect(&mut db_trans, product_id, reason, &detail, required).await? {
+            switched.push((product_id, to));
+        }
+    }
+    db_trans.commit().await?;
+    Ok(switched)
+}
@@ -0,0 +1,112 @@
+use crate::config::Settings;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::{query, query_as, FromRow, SqlitePool};
+use std::collections::HashMap;
+use time::{serde::rfc3339 as show_time, OffsetDateTime};
+
+#[derive(Serialize, FromRow)]
+pub struct Supplier {
+    pub id: i64,
+    pub name: String,
+    pub store_url: String,
+    pub offer_count: i64,
+    pub delisted_count: i64,
+    pub sale30: i64,
+    pub price_change_rate: f64,
+    pub sku_change_rate: f64,
+    pub score: i64,
+    pub blacklisted: i64,
+    pub tips: String,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "show_time")]
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(FromRow)]
+struct OfferRow {
+    supplier: String,
+    store_url: String,
+    sale30: i64,
+    price_changes: i64,
+    sku_changes: i64,
+    delisted: bool,
+    created_at: OffsetDateTime,
+    updated_at: OffsetDateTime,
+}
+
+#[derive(Default)]
+struct Stats {
+    store_url: String,
+    offer_count: i64,
+    delisted_count: i64,
+    sale30: i64,
+    price_changes: i64,
+    sku_changes: i64,
+    months: f64,
+}
+
+//可靠性评分: 下架比例越高、变价和改SKU越频繁, 分数越低
+fn score(settings: &Settings, s: &Stats) -> (f64, f64, i64) {
+    let price_penalty = settings.supplier_price_change_penalty;
+    let sku_penalty = settings.supplier_sku_change_penalty;
+    let price_rate = s.price_changes as f64 / s.months;
+    let sku_rate = s.sku_changes as f64 / s.months;
+    let score = 100.0 * (1.0 - s.delisted_count as f64 / s.offer_count as f64)
+        - price_penalty * price_rate
+        - sku_penalty * sku_rate;
+    (price_rate, sku_rate, score.round().clamp(0.0, 100.0) as i64)
+}
+
+//按offers重新统计供货商, 拉黑状态和备注保留
+pub async fn refresh(db: &SqlitePool, settings: &Settings) -> Result<Value> {
+    let offers: Vec<OfferRow> = query_as("select supplier, store_url, sale30, price_changes, sku_changes, deleted_at is not null or unavailable_at is not null as delisted, created_at, updated_at from offers where supplier != ''")
+        .fetch_all(db)
+        .await?;
+    let mut stats: HashMap<String, Stats> = HashMap::new();
+    for o in offers {
+        let s = stats.entry(o.supplier).or_default();
+        if s.store_url.is_empty() {
+            s.store_url = o.store_url;
+        }
+        s.offer_count += 1;
+        s.sale30 += o.sale30;
+        s.price_changes += o.price_changes;
+        s.sku_changes += o.sku_changes;
+        if o.delisted {
+            s.delisted_count += 1;
+        }
+        //采集的时间跨度, 按月计, 新offer至少按1个月算, 避免刚采集时变价一次就扣很多分
+        s.months += (o.updated_at - o.created_at).whole_days().max(30) as f64 / 30.0;
+    }
+
+    let now = OffsetDateTime::now_local()?;
+    let mut db_trans = db.begin().await?;
+    for (name, s) in stats.iter() {
+        let (price_rate, sku_rate, score) = score(settings, s);
+        query("insert into suppliers (name,store_url,offer_count,delisted_count,sale30,price_change_rate,sku_change_rate,score,created_at,updated_at) values (?,?,?,?,?,?,?,?,?,?) on conflict(name) do update set store_url=excluded.store_url,offer_count=excluded.offer_count,delisted_count=excluded.delisted_count,sale30=excluded.sale30,price_change_rate=excluded.price_change_rate,sku_change_rate=excluded.sku_change_rate,score=excluded.score,updated_at=excluded.updated_at")
+            .bind(name)
+            .bind(&s.store_url)
+            .bind(s.offer_count)
+            .bind(s.delisted_count)
+            .bind(s.sale30)
+            .bind(price_rate)
+            .bind(sku_rate)
+            .bind(score)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *db_trans)
+            .await?;
+    }
+    //已经没有offer的供货商
+    let emptied = query("update suppliers set offer_count=0,delisted_count=0,sale30=0,price_change_rate=0,sku_change_rate=0,updated_at=? where updated_at<?")
+        .bind(now)
+        .bind(now)
+        .execute(&mut *db_trans)
+        .await?
+        .rows_affected();
+    db_trans.commit().await?;
+    Ok(json!({ "suppliers": stats.len(), "emptied": emptied }))
+}
@@ -0,0 +1,169 @@
+use crate::analytics::DateRange;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{query, query_as, FromRow, SqlitePool};
+use time::{
+    serde::rfc3339 as show_time, serde::rfc3339::option as show_option_time, Duration,
+    OffsetDateTime,
+};
+
+#[derive(Serialize, FromRow)]
+pub struct TrackingEvent {
+    pub id: i64,
+    pub order_id: i64,
+    pub lg_order_id: String,
+    pub status: String,
+    pub location: String,
+    pub delivered: i64,
+    #[serde(with = "show_time")]
+    pub event_time: OffsetDateTime,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct NewEvent {
+    pub status: String,
+    #[serde(default)]
+    pub location: String,
+    #[serde(with = "show_time")]
+    pub time: OffsetDateTime,
+    //签收事件
+    #[serde(default)]
+    pub delivered: bool,
+}
+
+//采集物流详情页时提交, 已记录的事件(订单+时间+状态相同)不重复添加
+#[derive(Deserialize)]
+pub struct Tracking {
+    pub order_id: i64,
+    //分包裹发货时为该包裹的物流单号, 默认为orders.lg_order_id
+    #[serde(default)]
+    pub lg_order_id: String,
+    #[serde(default)]
+    pub destination: String,
+    pub events: Vec<NewEvent>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct Stuck {
+    pub order_id: i64,
+    pub lg_order_id: Option<String>,
+    pub destination: String,
+    #[serde(with = "show_option_time")]
+    pub shipped_at: Option<OffsetDateTime>,
+    #[serde(with = "show_option_time")]
+    pub last_event_at: Option<OffsetDateTime>,
+    pub last_status: String,
+    #[serde(with = "show_time")]
+    pub created_at: OffsetDateTime,
+}
+
+//记录物流事件, 并更新订单的发货/最近事件/签收时间和运输天数, 返回新增的事件数
+pub async fn record(db: &SqlitePool, tracking: &Tracking) -> Result<Option<u64>> {
+    let order: Option<(Option<String>,)> =
+        query_as("select lg_order_id from orders where order_id=?")
+            .bind(tracking.order_id)
+            .fetch_optional(db)
+            .await?;
+    let lg_order_id = match order {
+        Some(_) if !tracking.lg_order_id.is_empty() => tracking.lg_order_id.clone(),
+        Some((lg_order_id,)) => lg_order_id.unwrap_or_default(),
+        None => {
+            return Ok(None);
+        }
+    };
+    //统一为本地时区, 保证按字符串比较时顺序正确
+    let now = OffsetDateTime::now_local()?;
+    let offset = now.offset();
+    let mut added = 0;
+    let mut db_trans = db.begin().await?;
+    for event in tracking.events.iter() {
+        added += query("insert into tracking_events (order_id,lg_order_id,status,location,delivered,event_time,created_at) values (?,?,?,?,?,?,?) on conflict(order_id,event_time,status) do nothing")
+            .bind(tracking.order_id)
+            .bind(&lg_order_id)
+            .bind(event.status.trim())
+            .bind(event.location.trim())
+            .bind(event.delivered)
+            .bind(event.time.to_offset(offset))
+            .bind(now)
+            .execute(&mut *db_trans)
+            .await?
+            .rows_affected();
+    }
+    let (shipped_at, last_event_at, delivered_at): (
+        Option<OffsetDateTime>,
+        Option<OffsetDateTime>,
+        Option<OffsetDateTime>,
+    ) = query_as("select min(event_time), max(event_time), min(case when delivered=1 then event_time end) from tracking_events where order_id=?")
+        .bind(tracking.order_id)
+        .fetch_one(&mut *db_trans)
+        .await?;
+    let transit_days = match (shipped_at, delivered_at) {
+        (Some(s), Some(d)) => ((d - s).whole_minutes() as f64 / 1440.0 * 10.0).round() / 10.0,
+        _ => 0.0,
+    };
+    query("update orders set destination=case when ?='' then destination else ? end,shipped_at=?,last_event_at=?,delivered_at=?,transit_days=? where order_id=?")
+        .bind(tracking.destination.trim())
+        .bind(tracking.destination.trim())
+        .bind(shipped_at)
+        .bind(last_event_at)
+        .bind(delivered_at)
+        .bind(transit_days)
+        .bind(tracking.order_id)
+        .execute(&mut *db_trans)
+        .await?;
+    db_trans.commit().await?;
+    Ok(Some(added))
+}
+
+pub async fn events(db: &SqlitePool, order_id: i64) -> Result<Vec<TrackingEvent>> {
+    Ok(
+        query_as("select * from tracking_events where order_id=? order by event_time asc")
+            .bind(order_id)
+            .fetch_all(db)
+            .await?,
+    )
+}
+
+//已有物流单号未签收, 超过days天没有新的物流事件
+//只看发货后max_days天内的包裹(没有物流事件的按下单时间)
+pub async fn stuck(db: &SqlitePool, days: i64, max_days: i64, limit: i64) -> Result<Vec<Stuck>> {
+    let now = OffsetDateTime::now_local()?;
+    Ok(query_as("select o.order_id, o.lg_order_id, o.destination, o.shipped_at, o.last_event_at, coalesce((select e.status from tracking_events e where e.order_id=o.order_id order by e.event_time desc limit 1),'') as last_status, o.created_at from orders o where o.lg_order_id is not null and o.delivered_at is null and coalesce(o.shipped_at, o.created_at) >= ? and coalesce(o.last_event_at, o.created_at) < ? order by coalesce(o.last_event_at, o.created_at) asc limit ?")
+        .bind(now - Duration::days(max_days))
+        .bind(now - Duration::days(days))
+        .bind(limit)
+        .fetch_all(db)
+        .await?)
+}
+
+//按目的地统计签收订单的平均运输天数
+pub async fn transit(db: &SqlitePool, range: DateRange) -> Result<Value> {
+    let rows: Vec<(String, i64, f64, f64, f64)> = query_as("select destination, count(id), avg(transit_days), min(transit_days), max(transit_days) from orders where delivered_at >= ? and delivered_at < ? and transit_days > 0 group by destination order by count(id) desc")
+        .bind(range.0)
+        .bind(range.1 + Duration::days(1))
+        .fetch_all(db)
+        .await?;
+    Ok(json!(rows
+        .iter()
+        .map(|r| json!({
+            "destination": r.0,
+            "orders": r.1,
+            "avg_days": (r.2 * 10.0).round() / 10.0,
+            "min_days": r.3,
+            "max_days": r.4,
+        }))
+        .collect::<Vec<Value>>()))
+}
+
+//删除已不存在的订单的物流事件
+pub async fn purge(db: &SqlitePool) -> Result<u64> {
+    Ok(
+        query("delete from tracking_events where order_id not in (select order_id from orders)")
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
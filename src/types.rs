@@ -1,18 +1,40 @@
+use crate::config::{Settings, SharedSettings};
 use anyhow::Error;
 use axum::{
-    extract::Json,
+    extract::{FromRef, Json},
     http::StatusCode,
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
 use serde_json::Value;
 use sqlx::SqlitePool;
+use std::sync::Arc;
 use tracing::{debug, error};
 
 #[derive(Clone)]
 pub struct AEState {
     pub db_pool: SqlitePool,
-    pub settings: Value,
+    pub settings: Arc<Settings>,
+}
+
+//路由的状态, settings可热加载, 每个请求取当时的settings生成AEState
+#[derive(Clone)]
+pub struct ServerState {
+    pub db_pool: SqlitePool,
+    pub settings: SharedSettings,
+}
+impl FromRef<ServerState> for AEState {
+    fn from_ref(state: &ServerState) -> Self {
+        Self {
+            db_pool: state.db_pool.clone(),
+            settings: state.settings.get(),
+        }
+    }
+}
+impl FromRef<ServerState> for SharedSettings {
+    fn from_ref(state: &ServerState) -> Self {
+        state.settings.clone()
+    }
 }
 
 #[derive(Debug)]